@@ -9,11 +9,26 @@ use fnv::FnvHashMap;
 
 use tantivity_term_map::map::TermHashMap;
 
+fn get_or_insert_prefer_get<'a, T, F>(map: *mut FnvHashMap<String, T>, key: &str, mut constructor: F) -> &'a mut T
+where
+    F: FnMut() -> T,
+{
+    unsafe {
+        if let Some(e) = (*map).get_mut(key) {
+            return e;
+        }
+
+        (*map).insert(key.to_string(), constructor());
+        (*map).get_mut(key).unwrap()
+    }
+}
+
 
 
 #[cfg(test)]
 mod tests {
 
+    #[allow(dead_code)]
     #[derive(Debug, Default, Clone, Copy)]
     struct MoreMetaData {
         counter1: usize,
@@ -24,6 +39,7 @@ mod tests {
     }
 
     use super::*;
+    use inohashmap::hasher::{crc32c_hasher, fnv32a_yoshimitsu_triad, HashFunction};
     use inohashmap::StringHashMap;
     
     use std::io::Read;
@@ -137,6 +153,39 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn bench_hasmap_full_crc32c(b: &mut Bencher) {
+        let contents = get_test_string_full();
+
+        b.iter(|| {
+            let mut map = StringHashMap::<u32>::with_power_of_two_size(10);
+            map.set_hash_function(HashFunction::Crc32c);
+            for text in contents.split_whitespace() {
+                let value = map.get_or_create(text, 0);
+                *value += 1;
+            }
+        });
+    }
+    #[bench]
+    fn bench_yoshimitsu_hash(b: &mut Bencher) {
+        let contents = get_test_string_full();
+
+        b.iter(|| {
+            contents
+                .split_whitespace()
+                .fold(0, |acc, text| acc ^ fnv32a_yoshimitsu_triad(1, text.as_bytes()))
+        });
+    }
+    #[bench]
+    fn bench_crc32c_hash(b: &mut Bencher) {
+        let contents = get_test_string_full();
+
+        b.iter(|| {
+            contents
+                .split_whitespace()
+                .fold(0, |acc, text| acc ^ crc32c_hasher(1, text.as_bytes()))
+        });
+    }
     #[bench]
     fn bench_fnv(b: &mut Bencher) {
         let contents = get_test_string();
@@ -162,17 +211,3 @@ mod tests {
         });
     }
 }
-
-fn get_or_insert_prefer_get<'a, T, F>(map: *mut FnvHashMap<String, T>, key: &str, mut constructor: F) -> &'a mut T
-where
-    F: FnMut() -> T,
-{
-    unsafe {
-        if let Some(e) = (*map).get_mut(key) {
-            return e;
-        }
-
-        (*map).insert(key.to_string(), constructor());
-        (*map).get_mut(key).unwrap()
-    }
-}
\ No newline at end of file
@@ -0,0 +1,162 @@
+//! Counting occurrences of a fixed vocabulary from many threads.
+
+use crate::{PerfectHashBuilder, PerfectHashMap, ShardedStringHashMap, StringHashMap};
+use core::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What `AtomicCounter` does with keys that are not in its vocabulary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownKeys {
+    Ignore,
+    /// Count them in a side map, see `AtomicCounter::unknown_keys`.
+    Collect,
+}
+
+/// Read-only key index with one `AtomicU64` counter per key.
+///
+/// Built from a finished `StringHashMap`, its keys are moved into a `PerfectHashMap`, so the
+/// counters are a dense array. Share it between threads with an `Arc`.
+///
+/// ```
+/// use inohashmap::{AtomicCounter, StringHashMap, UnknownKeys};
+/// use std::sync::Arc;
+///
+/// let mut vocabulary = StringHashMap::<u32>::new();
+/// vocabulary.get_or_create("blub", 0);
+/// let counter = Arc::new(AtomicCounter::new(vocabulary, UnknownKeys::Ignore));
+/// let handle = {
+///     let counter = Arc::clone(&counter);
+///     std::thread::spawn(move || counter.increment("blub"))
+/// };
+/// assert!(handle.join().unwrap());
+/// assert!(!counter.increment("blub2"));
+/// assert_eq!(counter.count("blub"), Some(1));
+/// ```
+#[derive(Debug)]
+pub struct AtomicCounter<T> {
+    keys: PerfectHashMap<T>,
+    counts: Box<[AtomicU64]>,
+    unknown: Option<ShardedStringHashMap<u64>>,
+}
+
+impl<T: Default + Clone + Debug> AtomicCounter<T> {
+    /// Takes the keys of `vocabulary`, all counters start at 0.
+    pub fn new(vocabulary: StringHashMap<T>, unknown_keys: UnknownKeys) -> Self {
+        let keys = PerfectHashBuilder::new().build(vocabulary);
+        let counts = (0..keys.len()).map(|_| AtomicU64::new(0)).collect();
+        let unknown = match unknown_keys {
+            UnknownKeys::Ignore => None,
+            UnknownKeys::Collect => Some(ShardedStringHashMap::new(16)),
+        };
+        AtomicCounter {
+            keys,
+            counts,
+            unknown,
+        }
+    }
+}
+
+impl<T> AtomicCounter<T> {
+    /// Adds 1 to the counter of `el`. Returns false if `el` is not in the vocabulary.
+    #[inline]
+    pub fn increment(&self, el: &str) -> bool {
+        self.add(el, 1)
+    }
+
+    /// Adds `count` to the counter of `el`. Returns false if `el` is not in the vocabulary.
+    #[inline]
+    pub fn add(&self, el: &str, count: u64) -> bool {
+        if let Some(index) = self.keys.index(el) {
+            self.counts[index].fetch_add(count, Ordering::Relaxed);
+            return true;
+        }
+        if let Some(unknown) = &self.unknown {
+            unknown.get_or_create_with(el, 0, |value| *value += count);
+        }
+        false
+    }
+
+    /// Returns the count of `el`, or `None` if it is not in the vocabulary.
+    #[inline]
+    pub fn count(&self, el: &str) -> Option<u64> {
+        self.keys
+            .index(el)
+            .map(|index| self.counts[index].load(Ordering::Relaxed))
+    }
+
+    /// Iterates over all keys of the vocabulary and their counts.
+    pub fn counts(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(move |(index, count)| (self.keys.key(index), count.load(Ordering::Relaxed)))
+    }
+
+    /// The vocabulary, its indices are the ones of the counters.
+    #[inline]
+    pub fn keys(&self) -> &PerfectHashMap<T> {
+        &self.keys
+    }
+
+    /// Counts of the keys outside the vocabulary, `None` with `UnknownKeys::Ignore`.
+    #[inline]
+    pub fn unknown_keys(&self) -> Option<&ShardedStringHashMap<u64>> {
+        self.unknown.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn vocabulary() -> StringHashMap<u32> {
+        let mut map = StringHashMap::new();
+        for (id, text) in ["a", "b", "c"].iter().enumerate() {
+            map.get_or_create(text, id as u32);
+        }
+        map
+    }
+
+    #[test]
+    fn count_from_threads() {
+        let counter = Arc::new(AtomicCounter::new(vocabulary(), UnknownKeys::Collect));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = Arc::clone(&counter);
+                std::thread::spawn(move || {
+                    for text in "a b b c c c d d e".split_whitespace() {
+                        counter.increment(text);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(counter.count("a"), Some(4));
+        assert_eq!(counter.count("c"), Some(12));
+        assert_eq!(counter.count("d"), None);
+        let mut counts: Vec<(&str, u64)> = counter.counts().collect();
+        counts.sort();
+        assert_eq!(counts, vec![("a", 4), ("b", 8), ("c", 12)]);
+        assert_eq!(counter.keys().get("b"), Some(&1));
+
+        let mut unknown = vec![];
+        for shard in counter.unknown_keys().unwrap().shards() {
+            unknown.extend(shard.iter().map(|(text, count)| (text.to_string(), *count)));
+        }
+        unknown.sort();
+        assert_eq!(unknown, vec![("d".to_string(), 8), ("e".to_string(), 4)]);
+    }
+
+    #[test]
+    fn ignore_unknown() {
+        let counter = AtomicCounter::new(vocabulary(), UnknownKeys::Ignore);
+        assert!(!counter.increment("d"));
+        assert!(counter.add("a", 3));
+        assert_eq!(counter.count("a"), Some(3));
+        assert!(counter.unknown_keys().is_none());
+    }
+}
@@ -0,0 +1,192 @@
+//! Bloom filter over the keys of a map, to reject most missing keys without the map.
+//!
+//! # Layout of `to_bytes`
+//!
+//! All integers are little-endian.
+//!
+//! ```text
+//! magic "INOB" | seed: u32 | num_hashes: u32 | num_words: u32 | num_words * u64
+//! ```
+
+use crate::frozen::{invalid_data, read_u32_le};
+use crate::hasher::{double_hashes, DEFAULT_SEED};
+use crate::StringHashMap;
+use core::fmt::Debug;
+use std::io;
+
+const MAGIC: &[u8; 4] = b"INOB";
+const HEADER_LEN: usize = 4 * 4;
+/// Upper bound of `num_hashes`, the optimum for about 43 bits per key.
+const MAX_NUM_HASHES: u32 = 30;
+
+impl<T: Default + Clone + Debug> StringHashMap<T> {
+    /// Builds a `BloomFilter` containing all keys, with `bits_per_key` bits per key.
+    ///
+    /// 10 bits per key give about 1% false positives, every 5 more bits divide them by 10.
+    pub fn to_bloom_filter(&self, bits_per_key: usize) -> BloomFilter {
+        let mut filter = BloomFilter::new(self.occupied, bits_per_key);
+        for (key, _) in self.iter() {
+            filter.insert(key);
+        }
+        filter
+    }
+}
+
+/// Set of strings answering `contains` with false positives but no false negatives.
+///
+/// Positions are computed by double hashing: `h1 + i * h2` for the `i`-th of `num_hashes`
+/// positions, where `h1` and `h2` are `fnv32a_yoshimitsu_triad` with two seeds derived from
+/// the seed of the filter.
+///
+/// ```
+/// use inohashmap::{BloomFilter, StringHashMap};
+/// let mut map = StringHashMap::<u32>::new();
+/// map.get_or_create("blub", 0);
+/// let bytes = map.to_bloom_filter(10).to_bytes();
+///
+/// let filter = BloomFilter::from_bytes(&bytes).unwrap();
+/// assert!(filter.contains("blub"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    seed: u32,
+    num_hashes: u32,
+    words: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `num_keys` keys with `bits_per_key` bits each.
+    pub fn new(num_keys: usize, bits_per_key: usize) -> Self {
+        let num_bits = num_keys.saturating_mul(bits_per_key).max(64);
+        let num_words = num_bits.div_ceil(64);
+        assert!(
+            num_words <= u32::MAX as usize,
+            "at most u32::MAX words are supported"
+        );
+        // `bits_per_key * ln 2` minimizes the false positive rate
+        let num_hashes = (bits_per_key as f64 * std::f64::consts::LN_2).round() as u32;
+        BloomFilter {
+            seed: DEFAULT_SEED,
+            num_hashes: num_hashes.clamp(1, MAX_NUM_HASHES),
+            words: vec![0; num_words],
+        }
+    }
+
+    #[inline]
+    pub fn num_bits(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    #[inline]
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    #[inline]
+    pub fn insert(&mut self, el: &str) {
+        for bit in self.bits(el) {
+            self.words[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns false if `el` was never inserted, true if it probably was.
+    #[inline]
+    pub fn contains(&self, el: &str) -> bool {
+        self.bits(el)
+            .all(|bit| self.words[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    #[inline]
+    fn bits(&self, el: &str) -> impl Iterator<Item = usize> {
+        let num_bits = self.num_bits() as u64;
+        let (h1, h2) = double_hashes(self.seed, el.as_bytes());
+        let (h1, h2) = (u64::from(h1), u64::from(h2));
+        (0..u64::from(self.num_hashes)).map(move |i| ((h1 + i * h2) % num_bits) as usize)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.words.len() * 8);
+        out.extend_from_slice(MAGIC);
+        for field in [self.seed, self.num_hashes, self.words.len() as u32] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        for word in &self.words {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    /// Reads the output of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not a bloom filter"));
+        }
+        let field = |i: usize| read_u32_le(&bytes[4 + i * 4..]);
+        let (seed, num_hashes, num_words) = (field(0), field(1), field(2) as usize);
+        if num_hashes == 0 || num_words == 0 {
+            return Err(invalid_data("empty bloom filter"));
+        }
+        if num_hashes > MAX_NUM_HASHES {
+            return Err(invalid_data("too many hashes"));
+        }
+        let words = &bytes[HEADER_LEN..];
+        if words.len() != num_words * 8 {
+            return Err(invalid_data("length does not match header"));
+        }
+        let words = words
+            .chunks_exact(8)
+            .map(|word| {
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(word);
+                u64::from_le_bytes(buf)
+            })
+            .collect();
+        Ok(BloomFilter {
+            seed,
+            num_hashes,
+            words,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn false_positive_rate() {
+        let contents = read_corpus();
+        let mut map = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            map.get_or_create(text, 0);
+        }
+
+        let filter = map.to_bloom_filter(10);
+        assert_eq!(filter.num_hashes(), 7);
+        let filter = BloomFilter::from_bytes(&filter.to_bytes()).unwrap();
+        assert!(map.iter().all(|(text, _)| filter.contains(text)));
+        let num_misses = 100_000;
+        let false_positives = (0..num_misses)
+            .filter(|i| filter.contains(&format!("missing{}", i)))
+            .count();
+        let rate = false_positives as f64 / num_misses as f64;
+        assert!(rate < 0.015, "false positive rate {}", rate);
+    }
+
+    #[test]
+    fn rejects_invalid_bytes() {
+        let filter = StringHashMap::<u32>::new().to_bloom_filter(10);
+        assert!(!filter.contains("blub"));
+        let bytes = filter.to_bytes();
+        assert_eq!(BloomFilter::from_bytes(&bytes).unwrap(), filter);
+        assert!(BloomFilter::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BloomFilter::from_bytes(&bytes[1..]).is_err());
+
+        let mut corrupted = bytes.clone();
+        corrupted[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(BloomFilter::from_bytes(&corrupted).is_err());
+        corrupted[8..12].copy_from_slice(&31u32.to_le_bytes());
+        assert!(BloomFilter::from_bytes(&corrupted).is_err());
+    }
+}
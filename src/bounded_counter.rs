@@ -0,0 +1,232 @@
+//! Counting an unbounded stream of strings in a fixed amount of memory.
+
+use crate::count_min_sketch::CountMinSketch;
+use crate::{StringHashMap, TableEntry};
+
+/// Smallest memory budget of a `BoundedCounter`.
+pub const MIN_MEMORY_BUDGET: usize = 4096;
+
+const DEPTH: usize = 4;
+
+/// Count of a key in the exact map.
+#[derive(Debug, Clone, Copy, Default)]
+struct Count {
+    /// estimate of the sketch when the key was admitted, 0 if it was never seen before
+    base: u32,
+    /// occurrences since the key was admitted
+    count: u32,
+}
+
+impl Count {
+    #[inline]
+    fn estimate(self) -> u32 {
+        self.base.saturating_add(self.count)
+    }
+}
+
+/// Counts keys exactly in a `StringHashMap` while they fit into the memory budget, and
+/// approximately in a `CountMinSketch` otherwise.
+///
+/// A quarter of the budget goes to the sketch. The rest holds the table of the map, twice as
+/// its rebuild allocates a second table, and its `string_data`, which are both allocated up
+/// front and never grow. When a key does not fit, the keys counted at most the mean count are
+/// evicted into the sketch, and from then on only keys estimated above that mean are admitted
+/// into the map. So the map keeps the current heavy hitters.
+///
+/// Estimates are never below the true count. They are exact for keys admitted before they
+/// were counted in the sketch, see `exact`.
+///
+/// ```
+/// use inohashmap::BoundedCounter;
+/// let mut counter = BoundedCounter::new(1 << 16);
+/// for text in "a b a c a".split_whitespace() {
+///     counter.add(text, 1);
+/// }
+/// assert_eq!(counter.exact("a"), Some(3));
+/// assert_eq!(counter.exact("d"), Some(0));
+/// assert!(counter.memory_usage() <= 1 << 16);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BoundedCounter {
+    memory_budget: usize,
+    map: StringHashMap<Count>,
+    sketch: CountMinSketch,
+    /// smallest estimate of a key to be admitted into `map`
+    min_admit_count: u32,
+}
+
+impl BoundedCounter {
+    /// `memory_budget` in bytes must be at least `MIN_MEMORY_BUDGET`.
+    pub fn new(memory_budget: usize) -> Self {
+        assert!(
+            memory_budget >= MIN_MEMORY_BUDGET,
+            "memory budget must be at least MIN_MEMORY_BUDGET"
+        );
+        let quarter = memory_budget / 4;
+        let sketch = CountMinSketch::new(
+            prev_power_of_two(quarter / (DEPTH * std::mem::size_of::<u32>())),
+            DEPTH,
+        );
+        let num_slots = prev_power_of_two(quarter / std::mem::size_of::<TableEntry<Count>>());
+        let mut map =
+            StringHashMap::with_power_of_two_size(num_slots.trailing_zeros() as usize + 1);
+        let table_size = num_slots * std::mem::size_of::<TableEntry<Count>>();
+        map.string_data = Vec::new();
+        map.string_data
+            .reserve_exact(memory_budget - sketch.memory_usage() - 2 * table_size);
+        BoundedCounter {
+            memory_budget,
+            map,
+            sketch,
+            min_admit_count: 0,
+        }
+    }
+
+    #[inline]
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    /// Peak bytes allocated by the map and the sketch, including the second table allocated
+    /// while the map is rebuilt by an eviction or a reseed.
+    pub fn memory_usage(&self) -> usize {
+        self.sketch.memory_usage()
+            + 2 * self.map.table.capacity() * std::mem::size_of::<TableEntry<Count>>()
+            + self.map.string_data.capacity()
+    }
+
+    /// Adds `count` occurrences of `el`.
+    pub fn add(&mut self, el: &str, count: u32) {
+        if let Some(counted) = self.map.get_mut(el) {
+            counted.count = counted.count.saturating_add(count);
+            return;
+        }
+        let base = self.sketch.estimate(el);
+        if base.saturating_add(count) >= self.min_admit_count {
+            if !self.has_room(el) {
+                self.evict();
+            }
+            if self.has_room(el) {
+                self.map.get_or_create(el, Count { base, count });
+                return;
+            }
+        }
+        self.sketch.add(el, count);
+    }
+
+    /// Estimated number of occurrences of `el`, never below the true number.
+    #[inline]
+    pub fn estimate(&self, el: &str) -> u32 {
+        match self.map.get(el) {
+            Some(counted) => counted.estimate(),
+            None => self.sketch.estimate(el),
+        }
+    }
+
+    /// The number of occurrences of `el`, if it is known exactly.
+    #[inline]
+    pub fn exact(&self, el: &str) -> Option<u32> {
+        match self.map.get(el) {
+            Some(counted) if counted.base == 0 => Some(counted.count),
+            Some(_) => None,
+            None if self.sketch.estimate(el) == 0 => Some(0),
+            None => None,
+        }
+    }
+
+    /// Iterates over the keys counted in the map and their estimates, in table order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.map
+            .iter()
+            .map(|(el, counted)| (el, counted.estimate()))
+    }
+
+    /// Whether `el` can be inserted into the map without growing the table or `string_data`.
+    #[inline]
+    fn has_room(&self, el: &str) -> bool {
+        // the condition of `get_or_create` to resize
+        let table_full = self.map.occupied as f32 * 1.5 > self.map.table.len() as f32;
+        let len = self.map.string_data.len() + varint_len(el.len()) + el.len();
+        !table_full && len <= self.map.string_data.capacity()
+    }
+
+    /// Moves the keys counted at most the mean count into the sketch.
+    #[cold]
+    fn evict(&mut self) {
+        if self.map.occupied == 0 {
+            return;
+        }
+        let sum: u64 = self
+            .map
+            .get_values()
+            .map(|counted| u64::from(counted.estimate()))
+            .sum();
+        let mean = (sum / self.map.occupied as u64) as u32;
+        let sketch = &mut self.sketch;
+        let keep = |el: &str, counted: &mut Count| {
+            if counted.estimate() > mean {
+                return true;
+            }
+            // `base` is already counted in the sketch
+            sketch.add(el, counted.count);
+            false
+        };
+        // in place, the allocations must not shrink and grow again
+        self.map.retain_in_place(keep, |_, _| {});
+        self.min_admit_count = mean.saturating_add(1);
+    }
+}
+
+/// Largest power of two not above `value`, which must not be 0.
+#[inline]
+fn prev_power_of_two(value: usize) -> usize {
+    1 << (usize::BITS - 1 - value.leading_zeros())
+}
+
+/// Number of bytes of `len` as varint.
+#[inline]
+fn varint_len(len: usize) -> usize {
+    (usize::BITS - (len | 1).leading_zeros()).div_ceil(7) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn memory_budget() {
+        let contents = read_corpus();
+        let budget = 1 << 16;
+        let mut counter = BoundedCounter::new(budget);
+        let memory_usage = counter.memory_usage();
+        assert!(memory_usage <= budget);
+        let mut counts = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            counter.add(text, 1);
+            *counts.get_or_create(text, 0) += 1;
+        }
+        assert_eq!(counter.memory_usage(), memory_usage);
+
+        for (text, &count) in counts.iter() {
+            assert!(counter.estimate(text) >= count);
+            if let Some(exact) = counter.exact(text) {
+                assert_eq!(exact, count);
+            }
+        }
+        let mut top: Vec<_> = counts.iter().collect();
+        top.sort_by_key(|&(text, &count)| (std::cmp::Reverse(count), text));
+        for &(text, &count) in &top[..10] {
+            assert_eq!(counter.exact(text), Some(count));
+        }
+        assert!(counter.iter().count() < counts.occupied);
+    }
+
+    #[test]
+    fn varint_len() {
+        let lens = [(0, 1), (127, 1), (128, 2), (16_383, 2), (16_384, 3)];
+        for &(len, expected) in &lens {
+            assert_eq!(super::varint_len(len), expected);
+        }
+    }
+}
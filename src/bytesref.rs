@@ -5,7 +5,7 @@ pub struct BytesRef(pub(crate) u32);
 impl BytesRef {
     #[inline]
     pub fn is_null(&self) -> bool {
-        self.0 == u32::max_value()
+        self.0 == u32::MAX
     }
 
     #[inline]
@@ -17,6 +17,6 @@ impl BytesRef {
 impl Default for BytesRef {
     #[inline]
     fn default() -> BytesRef {
-        BytesRef(u32::max_value())
+        BytesRef(u32::MAX)
     }
 }
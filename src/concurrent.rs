@@ -0,0 +1,641 @@
+//! Lock-free insert-only string map for atomic values.
+//!
+//! Every key is written once into an append-only arena, together with its value:
+//!
+//! ```text
+//! entry: V (aligned) | varint length | key bytes
+//! ```
+//!
+//! The table only holds the `BytesRef` address of each entry in an `AtomicU32`. Inserting a key
+//! claims an empty slot with a CAS on that word. Values never move, so references handed out by
+//! `get_or_insert` stay valid across resizes.
+//!
+//! A resize allocates a table of twice the size and every thread inserting into the map helps
+//! to migrate blocks of slots. Empty slots of the old table are sealed, by the migration or by
+//! an insert that finds one at the end of its probe sequence, so inserts racing with it either
+//! land before the seal and are migrated, or go to the new table. No thread waits for another
+//! one: until all blocks are migrated, inserts and lookups continue in the new table when they
+//! reach a sealed slot. Old tables are kept until the map is dropped, readers may still hold
+//! them.
+//!
+//! An insert that loses the race for a key leaves its entry unused in the arena, its value is
+//! dropped right away.
+
+use crate::hasher::{fnv32a_yoshimitsu_triad, random_seed};
+use crate::QuadraticProbing;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+use vint32::encode_varint_into_writer;
+
+const EMPTY: u32 = u32::MAX;
+/// Empty slot of a table that is being migrated.
+const MOVED: u32 = u32::MAX - 1;
+
+const CHUNK_BITS: u32 = 20;
+const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+const NUM_CHUNKS: usize = 1 << (32 - CHUNK_BITS);
+/// Number of slots migrated at once by a thread.
+const MIGRATION_BLOCK: usize = 1024;
+
+/// Concurrent insert-only map from strings to values like `AtomicU64`, without locks.
+///
+/// Entries, key and value, must fit into a 1MB arena chunk. The arena is limited to 4GB.
+pub struct ConcurrentStringHashMap<V> {
+    table: AtomicPtr<Table>,
+    /// first table, the others are reachable through `Table::next`
+    tables: *mut Table,
+    arena: Arena,
+    /// number of keys
+    len: AtomicUsize,
+    /// seed of `fnv32a_yoshimitsu_triad`, random per instance
+    seed: u32,
+    _values: PhantomData<V>,
+}
+
+unsafe impl<V: Send + Sync> Send for ConcurrentStringHashMap<V> {}
+unsafe impl<V: Send + Sync> Sync for ConcurrentStringHashMap<V> {}
+
+impl<V: Default + Send + Sync> Default for ConcurrentStringHashMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Default + Send + Sync> ConcurrentStringHashMap<V> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_power_of_two_size(10)
+    }
+
+    /// Same table size as `StringHashMap::with_power_of_two_size`.
+    pub fn with_power_of_two_size(power_of_two: usize) -> Self {
+        let table = Box::into_raw(Table::new(power_of_two));
+        ConcurrentStringHashMap {
+            table: AtomicPtr::new(table),
+            tables: table,
+            arena: Arena::new(),
+            len: AtomicUsize::new(0),
+            seed: random_seed(),
+            _values: PhantomData,
+        }
+    }
+
+    /// Returns the value of `el`, inserting `V::default()` if `el` is new.
+    pub fn get_or_insert(&self, el: &str) -> &V {
+        let hash = fnv32a_yoshimitsu_triad(self.seed, el.as_bytes());
+        // arena entry written by this call, reused if an insert is retried in another table
+        let mut new_addr = None;
+        let mut table = self.current_table();
+        let addr = loop {
+            match self.insert_in(table, el, hash, &mut new_addr) {
+                Ok(addr) => break addr,
+                Err(next) => table = next,
+            }
+        };
+        if let Some(unused) = new_addr.filter(|&new_addr| new_addr != addr) {
+            // another thread inserted `el` first, the entry of this call was never published
+            unsafe { ptr::drop_in_place(self.arena.ptr(unused) as *mut V) };
+        }
+        unsafe { self.value(addr) }
+    }
+
+    /// Returns the value of `el`, if it was inserted.
+    pub fn get(&self, el: &str) -> Option<&V> {
+        let hash = fnv32a_yoshimitsu_triad(self.seed, el.as_bytes());
+        let mut table = self.current_table();
+        loop {
+            let mut probe = QuadraticProbing::compute(hash >> table.bitshift, table.mask);
+            for _ in 0..table.slots.len() {
+                let addr = table.slots[probe.next_probe() as usize].load(Ordering::Acquire);
+                if addr == EMPTY {
+                    return None;
+                } else if addr == MOVED {
+                    break;
+                } else if unsafe { self.key(addr) } == el.as_bytes() {
+                    return Some(unsafe { self.value(addr) });
+                }
+            }
+            // `el` may have been inserted into the next table
+            table = next_table(table)?;
+        }
+    }
+
+    /// Number of keys.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over keys and values. Keys inserted while iterating may be missed.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        self.addrs().map(move |addr| unsafe {
+            (
+                std::str::from_utf8_unchecked(self.key(addr)),
+                self.value(addr),
+            )
+        })
+    }
+
+    /// Returns the address of the entry of `el`, or the next table if `el` has to be inserted
+    /// there because `table` is being resized.
+    fn insert_in<'a>(
+        &'a self,
+        table: &'a Table,
+        el: &str,
+        hash: u32,
+        new_addr: &mut Option<u32>,
+    ) -> Result<u32, &'a Table> {
+        if next_table(table).is_some() {
+            self.help_resize(table);
+        }
+        let mut probe = QuadraticProbing::compute(hash >> table.bitshift, table.mask);
+        for _ in 0..table.slots.len() {
+            let slot = &table.slots[probe.next_probe() as usize];
+            let mut current = slot.load(Ordering::Acquire);
+            if current == EMPTY {
+                // once a resize started, the end of the probe sequence is sealed instead, so
+                // `el` can't be inserted here after it is inserted into the next table
+                let new = match next_table(table) {
+                    None => *new_addr.get_or_insert_with(|| self.write_entry(el)),
+                    Some(_) => MOVED,
+                };
+                match slot.compare_exchange(EMPTY, new, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) if new == MOVED => current = MOVED,
+                    Ok(_) => {
+                        self.len.fetch_add(1, Ordering::Relaxed);
+                        self.count_slot(table);
+                        return Ok(new);
+                    }
+                    Err(actual) => current = actual,
+                }
+            }
+            if current == MOVED {
+                return Err(self.next_table_or_resize(table));
+            }
+            if unsafe { self.key(current) } == el.as_bytes() {
+                return Ok(current);
+            }
+        }
+        // probed every slot
+        Err(self.next_table_or_resize(table))
+    }
+
+    /// Counts an occupied slot of `table` and starts a resize when it gets too full.
+    #[inline]
+    fn count_slot(&self, table: &Table) {
+        let occupied = table.occupied.fetch_add(1, Ordering::Relaxed) + 1;
+        if occupied as f32 * 1.5 > table.slots.len() as f32 {
+            self.start_resize(table);
+        }
+    }
+
+    #[cold]
+    fn next_table_or_resize<'a>(&self, table: &'a Table) -> &'a Table {
+        self.start_resize(table);
+        next_table(table).expect("a resize started")
+    }
+
+    #[cold]
+    fn start_resize(&self, table: &Table) {
+        if next_table(table).is_some() {
+            return;
+        }
+        let next = Box::into_raw(Table::new(32 - table.bitshift as usize + 1));
+        let swapped =
+            table
+                .next
+                .compare_exchange(ptr::null_mut(), next, Ordering::AcqRel, Ordering::Acquire);
+        if swapped.is_err() {
+            drop(unsafe { Box::from_raw(next) });
+        }
+    }
+
+    /// Migrates blocks of `table` until none are left to claim. Doesn't wait for blocks claimed
+    /// by other threads, inserts go to the next table in the meantime. Whoever sees the
+    /// migration finished installs the next table.
+    #[cold]
+    fn help_resize(&self, table: &Table) {
+        let next_table = match next_table(table) {
+            Some(next_table) => next_table,
+            None => return,
+        };
+        let num_blocks = table.slots.len().div_ceil(MIGRATION_BLOCK);
+        while table.claimed_blocks.load(Ordering::Acquire) < num_blocks {
+            let block = table.claimed_blocks.fetch_add(1, Ordering::AcqRel);
+            if block >= num_blocks {
+                break;
+            }
+            let end = ((block + 1) * MIGRATION_BLOCK).min(table.slots.len());
+            for slot in &table.slots[block * MIGRATION_BLOCK..end] {
+                self.migrate_slot(slot, next_table);
+            }
+            table.migrated_blocks.fetch_add(1, Ordering::AcqRel);
+        }
+        if table.migrated_blocks.load(Ordering::Acquire) == num_blocks {
+            let _ = self.table.compare_exchange(
+                table as *const Table as *mut Table,
+                next_table as *const Table as *mut Table,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+        }
+    }
+
+    fn migrate_slot(&self, slot: &AtomicU32, next_table: &Table) {
+        let addr = match slot.compare_exchange(EMPTY, MOVED, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) | Err(MOVED) => return,
+            Err(addr) => addr,
+        };
+        let hash = fnv32a_yoshimitsu_triad(self.seed, unsafe { self.key(addr) });
+        let mut table = next_table;
+        // the next table may be resized itself, then its sealed slots forward to its next table
+        'tables: loop {
+            let mut probe = QuadraticProbing::compute(hash >> table.bitshift, table.mask);
+            for _ in 0..table.slots.len() {
+                let slot = &table.slots[probe.next_probe() as usize];
+                match slot.compare_exchange(EMPTY, addr, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => {
+                        self.count_slot(table);
+                        return;
+                    }
+                    Err(MOVED) => {
+                        table = self.next_table_or_resize(table);
+                        continue 'tables;
+                    }
+                    Err(_) => {}
+                }
+            }
+            table = self.next_table_or_resize(table);
+        }
+    }
+
+    /// Writes `V::default()` and `el` into the arena.
+    fn write_entry(&self, el: &str) -> u32 {
+        let mut header = [0u8; 5];
+        let mut writer = &mut header[..];
+        encode_varint_into_writer(&mut writer, el.len() as u32).unwrap();
+        let header_len = 5 - writer.len();
+
+        let (addr, entry) = self
+            .arena
+            .alloc(size_of::<V>() + header_len + el.len(), align_of::<V>());
+        unsafe {
+            ptr::write(entry as *mut V, V::default());
+            let key = entry.add(size_of::<V>());
+            ptr::copy_nonoverlapping(header.as_ptr(), key, header_len);
+            ptr::copy_nonoverlapping(el.as_ptr(), key.add(header_len), el.len());
+        }
+        addr
+    }
+}
+
+impl<V> ConcurrentStringHashMap<V> {
+    #[inline]
+    fn current_table(&self) -> &Table {
+        unsafe { &*self.table.load(Ordering::Acquire) }
+    }
+
+    /// Addresses of all published entries. An entry is in the table it was inserted into and
+    /// in the tables it was migrated to, it is only returned for the first of them. A migration
+    /// may skip tables which were sealed already, so all earlier tables are checked.
+    fn addrs(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut tables = vec![self.current_table()];
+        while let Some(next) = next_table(tables[tables.len() - 1]) {
+            tables.push(next);
+        }
+        (0..tables.len()).flat_map(move |i| {
+            let (earlier, table) = (tables[..i].to_vec(), tables[i]);
+            table
+                .slots
+                .iter()
+                .map(|slot| slot.load(Ordering::Acquire))
+                .filter(|&addr| addr != EMPTY && addr != MOVED)
+                .filter(move |&addr| !earlier.iter().any(|earlier| self.contains(earlier, addr)))
+        })
+    }
+
+    /// Whether the entry `addr` is in `table`.
+    fn contains(&self, table: &Table, addr: u32) -> bool {
+        let hash = fnv32a_yoshimitsu_triad(self.seed, unsafe { self.key(addr) });
+        let mut probe = QuadraticProbing::compute(hash >> table.bitshift, table.mask);
+        for _ in 0..table.slots.len() {
+            let current = table.slots[probe.next_probe() as usize].load(Ordering::Acquire);
+            if current == addr {
+                return true;
+            } else if current == EMPTY || current == MOVED {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// # Safety
+    /// `addr` must be a published entry.
+    #[inline]
+    unsafe fn key(&self, addr: u32) -> &[u8] {
+        let mut pos = self.arena.ptr(addr).add(size_of::<V>());
+        let mut length_string = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = *pos;
+            pos = pos.add(1);
+            length_string |= u32::from(byte & 127) << shift;
+            if byte & 128 == 0 {
+                break;
+            }
+        }
+        std::slice::from_raw_parts(pos, length_string as usize)
+    }
+
+    /// # Safety
+    /// `addr` must be a published entry.
+    #[inline]
+    unsafe fn value(&self, addr: u32) -> &V {
+        &*(self.arena.ptr(addr) as *const V)
+    }
+}
+
+impl<V> Drop for ConcurrentStringHashMap<V> {
+    fn drop(&mut self) {
+        if std::mem::needs_drop::<V>() {
+            let addrs: Vec<u32> = self.addrs().collect();
+            for addr in addrs {
+                unsafe { ptr::drop_in_place(self.arena.ptr(addr) as *mut V) };
+            }
+        }
+        let mut table = self.tables;
+        while !table.is_null() {
+            let boxed = unsafe { Box::from_raw(table) };
+            table = boxed.next.load(Ordering::Acquire);
+        }
+    }
+}
+
+/// The table `table` is migrated to, if a resize started.
+#[inline]
+fn next_table(table: &Table) -> Option<&Table> {
+    unsafe { table.next.load(Ordering::Acquire).as_ref() }
+}
+
+struct Table {
+    slots: Box<[AtomicU32]>,
+    bitshift: u32,
+    mask: u32,
+    occupied: AtomicUsize,
+    /// table this one is migrated to, set once
+    next: AtomicPtr<Table>,
+    claimed_blocks: AtomicUsize,
+    migrated_blocks: AtomicUsize,
+}
+
+impl Table {
+    fn new(power_of_two: usize) -> Box<Table> {
+        let num_slots = 1 << (power_of_two - 1);
+        Box::new(Table {
+            slots: (0..num_slots).map(|_| AtomicU32::new(EMPTY)).collect(),
+            bitshift: 32 - power_of_two as u32,
+            mask: num_slots as u32 - 1,
+            occupied: AtomicUsize::new(0),
+            next: AtomicPtr::new(ptr::null_mut()),
+            claimed_blocks: AtomicUsize::new(0),
+            migrated_blocks: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// Append-only memory addressed by `u32`, split into lazily allocated chunks.
+struct Arena {
+    chunks: Box<[AtomicPtr<u64>]>,
+    next: AtomicUsize,
+}
+
+impl Arena {
+    fn new() -> Self {
+        Arena {
+            chunks: (0..NUM_CHUNKS)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
+                .collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserves `len` bytes aligned to `align` within one chunk.
+    fn alloc(&self, len: usize, align: usize) -> (u32, *mut u8) {
+        assert!(len <= CHUNK_SIZE, "entry does not fit into an arena chunk");
+        let mut current = self.next.load(Ordering::Relaxed);
+        let start = loop {
+            let mut start = (current + align - 1) & !(align - 1);
+            if (start & (CHUNK_SIZE - 1)) + len > CHUNK_SIZE {
+                start = (start & !(CHUNK_SIZE - 1)) + CHUNK_SIZE;
+            }
+            let end = start + len;
+            assert!(end <= MOVED as usize, "string data exceeds 4GB");
+            match self.next.compare_exchange_weak(
+                current,
+                end,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break start,
+                Err(actual) => current = actual,
+            }
+        };
+        let chunk = self.chunk(start >> CHUNK_BITS);
+        (start as u32, unsafe { chunk.add(start & (CHUNK_SIZE - 1)) })
+    }
+
+    fn chunk(&self, index: usize) -> *mut u8 {
+        let chunk = self.chunks[index].load(Ordering::Acquire);
+        if !chunk.is_null() {
+            return chunk as *mut u8;
+        }
+        let new_chunk = Box::into_raw(vec![0u64; CHUNK_SIZE / 8].into_boxed_slice()) as *mut u64;
+        match self.chunks[index].compare_exchange(
+            ptr::null_mut(),
+            new_chunk,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => new_chunk as *mut u8,
+            Err(chunk) => {
+                drop(unsafe { free_chunk(new_chunk) });
+                chunk as *mut u8
+            }
+        }
+    }
+
+    /// Pointer to `addr`, which must be in an allocated chunk.
+    #[inline]
+    fn ptr(&self, addr: u32) -> *mut u8 {
+        let addr = addr as usize;
+        let chunk = self.chunks[addr >> CHUNK_BITS].load(Ordering::Acquire) as *mut u8;
+        unsafe { chunk.add(addr & (CHUNK_SIZE - 1)) }
+    }
+}
+
+/// # Safety
+/// `chunk` must come from `Arena::chunk` and not be used afterwards.
+unsafe fn free_chunk(chunk: *mut u64) -> Box<[u64]> {
+    Box::from_raw(ptr::slice_from_raw_parts_mut(chunk, CHUNK_SIZE / 8))
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for chunk in self.chunks.iter() {
+            let chunk = chunk.load(Ordering::Acquire);
+            if !chunk.is_null() {
+                drop(unsafe { free_chunk(chunk) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+    use crate::StringHashMap;
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn count_from_threads() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let mut expected = StringHashMap::<u64>::new();
+        for text in &words {
+            *expected.get_or_create(text, 0) += 1;
+        }
+
+        let map = ConcurrentStringHashMap::<AtomicU64>::with_power_of_two_size(1);
+        let first = map.get_or_insert(words[0]);
+        std::thread::scope(|scope| {
+            for chunk in words.chunks(words.len() / 8 + 1) {
+                let map = &map;
+                scope.spawn(move || {
+                    for text in chunk {
+                        map.get_or_insert(text).fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        first.fetch_add(1, Ordering::Relaxed);
+
+        assert_eq!(map.len(), expected.occupied);
+        assert_eq!(map.iter().count(), expected.occupied);
+        for (text, value) in expected.iter() {
+            let extra = u64::from(text == words[0]);
+            assert_eq!(
+                map.get(text).unwrap().load(Ordering::Relaxed),
+                value + extra
+            );
+        }
+        assert!(map.get("not a word in the text").is_none());
+    }
+
+    #[test]
+    fn insert_while_a_helper_stalls() {
+        let map = ConcurrentStringHashMap::<AtomicU64>::with_power_of_two_size(1);
+        map.get_or_insert("first").fetch_add(1, Ordering::Relaxed);
+        // the single slot filled the table, a thread claims the only block and stalls
+        let table = map.current_table();
+        assert!(next_table(table).is_some());
+        table.claimed_blocks.fetch_add(1, Ordering::AcqRel);
+
+        let keys: Vec<String> = (0..1000u32).map(|i| i.to_string()).collect();
+        for key in &keys {
+            map.get_or_insert(key).fetch_add(1, Ordering::Relaxed);
+        }
+        let check = |map: &ConcurrentStringHashMap<AtomicU64>| {
+            assert_eq!(map.len(), keys.len() + 1);
+            assert_eq!(map.iter().count(), keys.len() + 1);
+            for key in keys.iter().map(String::as_str).chain(Some("first")) {
+                assert_eq!(map.get(key).unwrap().load(Ordering::Relaxed), 1);
+                assert_eq!(map.get_or_insert(key).load(Ordering::Relaxed), 1);
+            }
+        };
+        check(&map);
+        assert!(std::ptr::eq(map.current_table(), table));
+
+        // the stalled thread resumes, "first" skips the next tables which are sealed already
+        map.migrate_slot(&table.slots[0], next_table(table).unwrap());
+        check(&map);
+        table.migrated_blocks.fetch_add(1, Ordering::AcqRel);
+        map.get_or_insert("first");
+        assert!(!std::ptr::eq(map.current_table(), table));
+        check(&map);
+    }
+
+    #[test]
+    fn values_are_dropped() {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        struct CountDrops(AtomicU64);
+        impl Default for CountDrops {
+            fn default() -> Self {
+                CREATED.fetch_add(1, Ordering::Relaxed);
+                CountDrops(AtomicU64::new(0))
+            }
+        }
+        impl Drop for CountDrops {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let map = ConcurrentStringHashMap::<CountDrops>::with_power_of_two_size(1);
+        for i in 0..100u32 {
+            map.get_or_insert(&i.to_string())
+                .0
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        assert_eq!(map.get("42").unwrap().0.load(Ordering::Relaxed), 1);
+        drop(map);
+        assert_eq!(CREATED.load(Ordering::Relaxed), 100);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 100);
+
+        // threads racing for the same keys drop the values of the entries they lose with
+        let map = ConcurrentStringHashMap::<CountDrops>::with_power_of_two_size(1);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for i in 0..1000u32 {
+                        map.get_or_insert(&i.to_string())
+                            .0
+                            .fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        let created = CREATED.load(Ordering::Relaxed) - 100;
+        assert_eq!(DROPPED.load(Ordering::Relaxed) - 100, created - 1000);
+        assert_eq!(map.len(), 1000);
+        assert!(map
+            .iter()
+            .all(|(_, value)| value.0.load(Ordering::Relaxed) == 8));
+        drop(map);
+        assert_eq!(
+            DROPPED.load(Ordering::Relaxed),
+            CREATED.load(Ordering::Relaxed)
+        );
+
+        // dropped while a stalled migration lags behind, with entries in several tables
+        let map = ConcurrentStringHashMap::<CountDrops>::with_power_of_two_size(1);
+        map.get_or_insert("first");
+        let table = map.current_table();
+        table.claimed_blocks.fetch_add(1, Ordering::AcqRel);
+        for i in 0..1000u32 {
+            map.get_or_insert(&i.to_string());
+        }
+        map.migrate_slot(&table.slots[0], next_table(table).unwrap());
+        assert_eq!(map.iter().count(), 1001);
+        drop(map);
+        assert_eq!(
+            DROPPED.load(Ordering::Relaxed),
+            CREATED.load(Ordering::Relaxed)
+        );
+    }
+}
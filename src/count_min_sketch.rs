@@ -0,0 +1,137 @@
+//! Approximate counts of strings in fixed memory, for streams with too many distinct keys.
+
+use crate::hasher::{double_hashes, DEFAULT_SEED};
+
+/// Count-min sketch: `depth` rows of `width` counters, a key adds its count to one counter per
+/// row and its estimate is the smallest of them.
+///
+/// The estimate is never below the true count and exceeds it by at most `e / width` of the
+/// total count with probability `1 - e^-depth`. The rows are indexed by double hashing the key
+/// with `fnv32a_yoshimitsu_triad`, with the same seed for all sketches, so sketches of the same
+/// size can be merged.
+///
+/// ```
+/// use inohashmap::CountMinSketch;
+/// let mut sketch = CountMinSketch::new(1024, 4);
+/// sketch.add("blub", 2);
+/// sketch.add("blub", 1);
+/// assert_eq!(sketch.estimate("blub"), 3);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountMinSketch {
+    depth: usize,
+    mask: u32,
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    /// `width` must be a power of two, `depth` between 1 and 16.
+    pub fn new(width: usize, depth: usize) -> Self {
+        assert!(
+            width.is_power_of_two() && width <= 1 << 31,
+            "width must be a power of two"
+        );
+        assert!((1..=16).contains(&depth), "depth must be between 1 and 16");
+        CountMinSketch {
+            depth,
+            mask: width as u32 - 1,
+            counters: vec![0; width * depth],
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.mask as usize + 1
+    }
+
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Bytes used by the counters.
+    #[inline]
+    pub fn memory_usage(&self) -> usize {
+        self.counters.capacity() * std::mem::size_of::<u32>()
+    }
+
+    /// Adds `count` occurrences of `el`, counters saturate at `u32::MAX`.
+    #[inline]
+    pub fn add(&mut self, el: &str, count: u32) {
+        for index in self.indices(el) {
+            let counter = &mut self.counters[index];
+            *counter = counter.saturating_add(count);
+        }
+    }
+
+    /// Estimated number of occurrences of `el`, never below the true number.
+    #[inline]
+    pub fn estimate(&self, el: &str) -> u32 {
+        self.indices(el)
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Adds the counts of `other`, which must have the same width and depth.
+    pub fn merge(&mut self, other: &CountMinSketch) {
+        assert!(
+            self.mask == other.mask && self.depth == other.depth,
+            "only sketches of the same size can be merged"
+        );
+        for (counter, &other) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(other);
+        }
+    }
+
+    #[inline]
+    fn indices(&self, el: &str) -> impl Iterator<Item = usize> {
+        let (h1, h2) = double_hashes(DEFAULT_SEED, el.as_bytes());
+        let (mask, width) = (self.mask, self.width());
+        (0..self.depth as u32).map(move |row| {
+            let column = h1.wrapping_add(row.wrapping_mul(h2)) & mask;
+            row as usize * width + column as usize
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+    use crate::StringHashMap;
+
+    #[test]
+    fn error_bound() {
+        let contents = read_corpus();
+        let mut counts = StringHashMap::<u32>::new();
+        let mut sketch = CountMinSketch::new(1 << 14, 4);
+        let (mut first, mut second) = (sketch.clone(), sketch.clone());
+        let mut total = 0;
+        for (i, text) in contents.split_whitespace().enumerate() {
+            *counts.get_or_create(text, 0) += 1;
+            sketch.add(text, 1);
+            if i % 2 == 0 {
+                first.add(text, 1);
+            } else {
+                second.add(text, 1);
+            }
+            total += 1;
+        }
+        first.merge(&second);
+        assert_eq!(first, sketch);
+
+        let bound = (std::f64::consts::E / sketch.width() as f64 * total as f64) as u32;
+        let mut num_above_bound = 0;
+        for (text, &count) in counts.iter() {
+            let estimate = sketch.estimate(text);
+            assert!(estimate >= count);
+            if estimate > count + bound {
+                num_above_bound += 1;
+            }
+        }
+        // expected share is `e^-4`, below 2%
+        assert!(num_above_bound * 50 < counts.occupied);
+        assert_eq!(sketch.memory_usage(), (1 << 14) * 4 * 4);
+    }
+}
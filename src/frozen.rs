@@ -0,0 +1,263 @@
+//! Read-only `StringHashMap` that answers lookups straight from a byte slice, e.g. an mmap'd file.
+//!
+//! # Layout
+//!
+//! All integers are little-endian `u32`.
+//!
+//! ```text
+//! header:      magic | seed | bitshift | num_slots | len | value_size | string_data_len
+//! table:       num_slots * BytesRef      (u32::MAX marks an empty slot)
+//! values:      num_slots * value_size    (FixedSizeValue encoding, zeroed for empty slots)
+//! string_data: string_data_len bytes     (varint length + utf-8 bytes per key)
+//! ```
+//!
+//! The magic is "INOH" for maps hashing with `fnv32a_yoshimitsu_triad` and "INOC" for
+//! `crc32c_hasher`. Slot positions are the ones of the `StringHashMap` that was frozen, so lookups
+//! hash and probe exactly like `get_or_create`.
+
+use crate::hasher::HashFunction;
+use crate::{read_key_bytes_checked, QuadraticProbing, StringHashMap};
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use std::io;
+
+const MAGIC_YOSHIMITSU: &[u8; 4] = b"INOH";
+const MAGIC_CRC32C: &[u8; 4] = b"INOC";
+const HEADER_LEN: usize = 7 * 4;
+const NULL_ADDR: u32 = u32::MAX;
+
+/// Value with a fixed size little-endian encoding, as stored in a frozen map.
+pub trait FixedSizeValue: Sized {
+    /// Number of bytes written by `write_to`.
+    const SIZE: usize;
+    /// Appends exactly `SIZE` bytes to `out`.
+    fn write_to(&self, out: &mut Vec<u8>);
+    /// Decodes a value from exactly `SIZE` bytes.
+    fn read_from(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_fixed_size_value {
+    ($($ty:ty),*) => {
+        $(
+            impl FixedSizeValue for $ty {
+                const SIZE: usize = std::mem::size_of::<$ty>();
+                #[inline]
+                fn write_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+                #[inline]
+                fn read_from(bytes: &[u8]) -> Self {
+                    let mut buf = [0u8; std::mem::size_of::<$ty>()];
+                    buf.copy_from_slice(bytes);
+                    <$ty>::from_le_bytes(buf)
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_size_value!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl<T: Default + Clone + Debug + FixedSizeValue> StringHashMap<T> {
+    /// Serializes the map into the layout read by `FrozenStringHashMap`.
+    pub fn freeze(&self) -> Vec<u8> {
+        let num_slots = self.table.len();
+        let mut out =
+            Vec::with_capacity(HEADER_LEN + num_slots * (4 + T::SIZE) + self.string_data.len());
+        out.extend_from_slice(match self.hash_function {
+            HashFunction::Yoshimitsu => MAGIC_YOSHIMITSU,
+            HashFunction::Crc32c => MAGIC_CRC32C,
+        });
+        for field in [
+            self.seed,
+            self.bitshift as u32,
+            num_slots as u32,
+            self.occupied as u32,
+            T::SIZE as u32,
+            self.string_data.len() as u32,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        for entry in &self.table {
+            out.extend_from_slice(&entry.pointer.addr().to_le_bytes());
+        }
+        for entry in &self.table {
+            if entry.pointer.is_null() {
+                out.resize(out.len() + T::SIZE, 0);
+            } else {
+                entry.value.write_to(&mut out);
+            }
+        }
+        out.extend_from_slice(&self.string_data);
+        out
+    }
+}
+
+/// Read-only map borrowing the output of `StringHashMap::freeze`.
+#[derive(Debug)]
+pub struct FrozenStringHashMap<'a, T> {
+    hash_function: HashFunction,
+    seed: u32,
+    bitshift: u32,
+    mask: u32,
+    len: usize,
+    table: &'a [u8],
+    values: &'a [u8],
+    string_data: &'a [u8],
+    _value: PhantomData<T>,
+}
+
+// not derived, that would require `T: Copy`
+impl<T> Clone for FrozenStringHashMap<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for FrozenStringHashMap<'_, T> {}
+
+impl<'a, T: FixedSizeValue> FrozenStringHashMap<'a, T> {
+    /// Checks the header and section sizes of `bytes`. The sections are borrowed, not copied.
+    pub fn new(bytes: &'a [u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid_data("not a frozen string hash map"));
+        }
+        let hash_function = match &bytes[..4] {
+            magic if magic == MAGIC_YOSHIMITSU => HashFunction::Yoshimitsu,
+            magic if magic == MAGIC_CRC32C => HashFunction::Crc32c,
+            _ => return Err(invalid_data("not a frozen string hash map")),
+        };
+        let field = |i: usize| read_u32_le(&bytes[4 + i * 4..]);
+        let (seed, bitshift, num_slots) = (field(0), field(1), field(2) as usize);
+        let (len, value_size, string_data_len) =
+            (field(3) as usize, field(4) as usize, field(5) as usize);
+        if value_size != T::SIZE {
+            return Err(invalid_data("value size does not match"));
+        }
+        if !num_slots.is_power_of_two() || bitshift >= 32 || len >= num_slots {
+            return Err(invalid_data("invalid table size"));
+        }
+        let table_end = HEADER_LEN + num_slots * 4;
+        let values_end = table_end + num_slots * value_size;
+        if bytes.len() != values_end + string_data_len {
+            return Err(invalid_data("length does not match header"));
+        }
+        Ok(FrozenStringHashMap {
+            hash_function,
+            seed,
+            bitshift,
+            mask: num_slots as u32 - 1,
+            len,
+            table: &bytes[HEADER_LEN..table_end],
+            values: &bytes[table_end..values_end],
+            string_data: &bytes[values_end..],
+            _value: PhantomData,
+        })
+    }
+
+    /// Number of keys in the map.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the value stored for `el`.
+    pub fn get(&self, el: &str) -> Option<T> {
+        let hash = self.hash_function.hash(self.seed, el.as_bytes()) >> self.bitshift;
+        let mut probe = QuadraticProbing::compute(hash, self.mask);
+        // bounded, so a corrupt table without empty slots cannot loop forever
+        for _ in 0..=self.mask {
+            let slot = probe.next_probe() as usize;
+            let addr = read_u32_le(&self.table[slot * 4..]);
+            if addr == NULL_ADDR {
+                return None;
+            }
+            if read_key_bytes_checked(self.string_data, addr) == Some(el.as_bytes()) {
+                let start = slot * T::SIZE;
+                return Some(T::read_from(&self.values[start..start + T::SIZE]));
+            }
+        }
+        None
+    }
+}
+
+#[inline]
+pub(crate) fn read_u32_le(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freeze_and_get() {
+        let mut map = StringHashMap::<u64>::with_power_of_two_size(1);
+        for i in 0..5_000u64 {
+            *map.get_or_create(&format!("key{}", i), i * 3) += 1;
+        }
+        let bytes = map.freeze();
+        let frozen = FrozenStringHashMap::<u64>::new(&bytes).unwrap();
+        assert_eq!(frozen.len(), 5_000);
+        for i in 0..5_000u64 {
+            assert_eq!(frozen.get(&format!("key{}", i)), Some(i * 3 + 1));
+        }
+        assert_eq!(frozen.get("key5000"), None);
+        assert_eq!(frozen.get(""), None);
+    }
+
+    #[test]
+    fn freeze_crc32c() {
+        let mut map = StringHashMap::<u32>::new();
+        map.set_hash_function(HashFunction::Crc32c);
+        for i in 0..1_000u32 {
+            map.get_or_create(&i.to_string(), i);
+        }
+        let bytes = map.freeze();
+        assert_eq!(&bytes[..4], MAGIC_CRC32C);
+        let frozen = FrozenStringHashMap::<u32>::new(&bytes).unwrap();
+        for i in 0..1_000u32 {
+            assert_eq!(frozen.get(&i.to_string()), Some(i));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bytes() {
+        let mut map = StringHashMap::<u32>::new();
+        map.get_or_create("blub", 1);
+        let bytes = map.freeze();
+        assert!(FrozenStringHashMap::<u64>::new(&bytes).is_err());
+        assert!(FrozenStringHashMap::<u32>::new(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FrozenStringHashMap::<u32>::new(&bytes[1..]).is_err());
+        assert_eq!(
+            FrozenStringHashMap::<u32>::new(&bytes).unwrap().get("blub"),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn frozen_layout_is_stable() {
+        let mut map = StringHashMap::<u32>::with_power_of_two_size_and_seed(4, 1);
+        for (i, text) in ["a", "blub", "hello world", "blub2"].iter().enumerate() {
+            map.get_or_create(text, i as u32);
+        }
+        // pins the layout and the slots of the keys, the same on every platform
+        let bytes = map.freeze();
+        assert_eq!(bytes.len(), 117);
+        assert_eq!(
+            crate::hasher::fnv64_yoshimitsu_hasher(&bytes),
+            0x26D7_9D43_B12E_5822
+        );
+    }
+}
@@ -1,90 +1,816 @@
+//! String hash functions.
+//!
+//! The hashes read the input in little-endian words on every platform, so a hash only depends on
+//! the seed and the bytes. Tables written by `StringHashMap::freeze` and the shard assignments of
+//! `ShardedStringHashMap::with_seed` can be persisted and read on machines of any endianness. The values are pinned by test vectors and
+//! are part of the format, they must not change between releases.
+
+use std::hash::{BuildHasher, Hasher};
 use std::ptr::copy_nonoverlapping;
 
 #[inline]
 fn rotl32(x: u32, r: u8) -> u32 {
-    (x << r) | (x >> (32 - r))
+    x.rotate_left(u32::from(r))
 }
 
+// Words are read as little-endian on every platform, see the module documentation.
+
 #[inline]
 fn read_u32_p(p: *const u8) -> u32 {
-    let mut out: u32 = 0;
+    let mut out = [0u8; 4];
     unsafe {
-        copy_nonoverlapping(p, &mut out as *mut u32 as *mut u8, 4);
+        copy_nonoverlapping(p, out.as_mut_ptr(), 4);
     }
-    out
+    u32::from_le_bytes(out)
+}
+
+#[inline]
+fn read_u64_p(p: *const u8) -> u64 {
+    let mut out = [0u8; 8];
+    unsafe {
+        copy_nonoverlapping(p, out.as_mut_ptr(), 8);
+    }
+    u64::from_le_bytes(out)
 }
 
 #[inline]
 fn read_u16_p(p: *const u8) -> u16 {
-    let mut out: u16 = 0;
+    let mut out = [0u8; 2];
     unsafe {
-        copy_nonoverlapping(p, &mut out as *mut u16 as *mut u8, 2);
+        copy_nonoverlapping(p, out.as_mut_ptr(), 2);
     }
-    out
+    u16::from_le_bytes(out)
+}
+
+/// Seed used by `fnv32a_yoshimitsu_hasher`.
+pub const DEFAULT_SEED: u32 = 0xD8AF_FD71;
+
+/// Seed drawn from the OS entropy std uses for `RandomState`, different on every call.
+pub fn random_seed() -> u32 {
+    use std::collections::hash_map::RandomState;
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 32) as u32 ^ hash as u32
 }
 
 #[inline(never)]
 pub fn fnv32a_yoshimitsu_hasher(bytes: &[u8]) -> u32 {
-    fnv32a_yoshimitsu_triad(0xD8AF_FD71, bytes)
+    fnv32a_yoshimitsu_triad(DEFAULT_SEED, bytes)
 }
 
 #[inline]
 pub fn fnv32a_yoshimitsu_triad(seed: u32, bytes: &[u8]) -> u32 {
-    let mut len: u32 = bytes.len() as u32;
-    let mut p: *const u8 = bytes.as_ptr();
+    let mut triad = Triad::new(seed, bytes.len());
+    let mut blocks = bytes.chunks_exact(24);
+    for block in &mut blocks {
+        triad.round(block.as_ptr());
+    }
+    triad.finish(blocks.remainder(), bytes.len() >= 24)
+}
 
-    const PRIME: u32 = 709_607;
-    let mut hash32_a: u32 = seed ^ 2_166_136_261;
-    let mut hash32_b: u32 = 2_166_136_261 + len;
-    let mut hash32_c: u32 = 2_166_136_261;
-    while len >= 24 {
-        hash32_a = (hash32_a ^ (rotl32(read_u32_p(p), 5) ^ read_u32_p(unsafe { p.offset(4) })))
-            .wrapping_mul(PRIME);
-        hash32_b = (hash32_b
+/// `fnv32a_yoshimitsu_triad` of the concatenation of `pieces`, `len` bytes in total.
+fn fnv32a_yoshimitsu_triad_pieces<'a>(
+    seed: u32,
+    len: usize,
+    pieces: impl Iterator<Item = &'a [u8]>,
+) -> u32 {
+    let mut triad = Triad::new(seed, len);
+    let mut block = [0u8; 24];
+    let mut filled = 0;
+    for mut piece in pieces {
+        while !piece.is_empty() {
+            if filled == 0 && piece.len() >= 24 {
+                triad.round(piece.as_ptr());
+                piece = &piece[24..];
+                continue;
+            }
+            let num_bytes = piece.len().min(24 - filled);
+            block[filled..filled + num_bytes].copy_from_slice(&piece[..num_bytes]);
+            filled += num_bytes;
+            piece = &piece[num_bytes..];
+            if filled == 24 {
+                triad.round(block.as_ptr());
+                filled = 0;
+            }
+        }
+    }
+    triad.finish(&block[..filled], len >= 24)
+}
+
+/// Length of the parts joined with `sep`.
+#[inline]
+pub(crate) fn joined_len(parts: &[&str], sep: &str) -> usize {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    len + sep.len() * parts.len().saturating_sub(1)
+}
+
+/// The parts with `sep` between them.
+#[inline]
+pub(crate) fn joined_pieces<'a>(
+    parts: &'a [&'a str],
+    sep: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    parts
+        .iter()
+        .enumerate()
+        .flat_map(move |(i, part)| [if i == 0 { "" } else { sep }, *part])
+}
+
+/// Two independent hashes of `bytes` for double hashing, `h1 + i * h2` for the `i`-th position.
+/// `h2` is odd, so the positions don't repeat within a power of two.
+#[inline]
+pub(crate) fn double_hashes(seed: u32, bytes: &[u8]) -> (u32, u32) {
+    (
+        fnv32a_yoshimitsu_triad(seed, bytes),
+        fnv32a_yoshimitsu_triad(seed ^ 0x9E37_79B9, bytes) | 1,
+    )
+}
+
+const PRIME: u32 = 709_607;
+
+/// State of `fnv32a_yoshimitsu_triad`, fed with blocks of 24 bytes.
+struct Triad {
+    hash32_a: u32,
+    hash32_b: u32,
+    hash32_c: u32,
+}
+
+impl Triad {
+    #[inline]
+    fn new(seed: u32, len: usize) -> Self {
+        Triad {
+            hash32_a: seed ^ 2_166_136_261,
+            hash32_b: 2_166_136_261 + len as u32,
+            hash32_c: 2_166_136_261,
+        }
+    }
+
+    /// Consumes the 24 bytes at `p`.
+    #[inline]
+    fn round(&mut self, p: *const u8) {
+        self.hash32_a = (self.hash32_a
+            ^ (rotl32(read_u32_p(p), 5) ^ read_u32_p(unsafe { p.offset(4) })))
+        .wrapping_mul(PRIME);
+        self.hash32_b = (self.hash32_b
             ^ (rotl32(read_u32_p(unsafe { p.offset(8) }), 5)
                 ^ read_u32_p(unsafe { p.offset(12) })))
         .wrapping_mul(PRIME);
-        hash32_c = (hash32_c
+        self.hash32_c = (self.hash32_c
             ^ (rotl32(read_u32_p(unsafe { p.offset(16) }), 5)
                 ^ read_u32_p(unsafe { p.offset(20) })))
         .wrapping_mul(PRIME);
+    }
+
+    /// Consumes the last `tail.len() < 24` bytes. `rounds` tells whether `round` was called.
+    #[inline]
+    fn finish(self, tail: &[u8], rounds: bool) -> u32 {
+        let Triad {
+            mut hash32_a,
+            mut hash32_b,
+            hash32_c,
+        } = self;
+        let len = tail.len();
+        let mut p = tail.as_ptr();
+        if !rounds {
+            hash32_a = (hash32_a ^ rotl32(hash32_c, 5)).wrapping_mul(PRIME);
+        }
+        //Cases 0. .31
+        if (len & 16) != 0 {
+            hash32_a = (hash32_a ^ (rotl32(read_u32_p(p), 5) ^ read_u32_p(unsafe { p.offset(4) })))
+                .wrapping_mul(PRIME);
+            hash32_b = (hash32_b
+                ^ (rotl32(read_u32_p(unsafe { p.offset(8) }), 5)
+                    ^ read_u32_p(unsafe { p.offset(12) })))
+            .wrapping_mul(PRIME);
+            p = unsafe { p.offset(16) };
+        }
+        //Cases 0. .15
+        if (len & 8) != 0 {
+            hash32_a = (hash32_a ^ read_u32_p(p)).wrapping_mul(PRIME);
+            hash32_b = (hash32_b ^ read_u32_p(unsafe { p.offset(4) })).wrapping_mul(PRIME);
+            p = unsafe { p.offset(8) };
+        }
+        //Cases:0. .7
+        if (len & 4) != 0 {
+            hash32_a = (hash32_a ^ u32::from(read_u16_p(p))).wrapping_mul(PRIME);
+            hash32_b =
+                (hash32_b ^ u32::from(read_u16_p(unsafe { p.offset(2) }))).wrapping_mul(PRIME);
+            p = unsafe { p.offset(4) };
+        }
+        // //Cases:0. .3
+        if (len & 2) != 0 {
+            hash32_a = (hash32_a ^ u32::from(read_u16_p(p))).wrapping_mul(PRIME);
+            p = unsafe { p.offset(2) };
+        }
+        if (len & 1) != 0 {
+            hash32_a = (hash32_a ^ u32::from(unsafe { *p })).wrapping_mul(PRIME);
+        }
+        hash32_a = (hash32_a ^ rotl32(hash32_b, 5)).wrapping_mul(PRIME);
+        hash32_a ^ (hash32_a >> 16)
+    }
+}
+
+#[inline(never)]
+pub fn fnv64_yoshimitsu_hasher(bytes: &[u8]) -> u64 {
+    fnv64_yoshimitsu_triad(u64::from(DEFAULT_SEED), bytes)
+}
+
+/// 64-bit variant of `fnv32a_yoshimitsu_triad`, for fingerprints and sketches where 32 bits
+/// collide too often.
+///
+/// The three lanes consume 24 bytes per round like the 32-bit hash, with one 64-bit word per
+/// lane, so short keys take the same number of multiplications. A final avalanche step mixes the
+/// lanes into all 64 bits.
+#[inline]
+pub fn fnv64_yoshimitsu_triad(seed: u64, bytes: &[u8]) -> u64 {
+    let mut len = bytes.len();
+    let mut p: *const u8 = bytes.as_ptr();
+
+    const MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+    const OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+    let mut hash64_a: u64 = seed ^ OFFSET;
+    let mut hash64_b: u64 = OFFSET.wrapping_add(len as u64);
+    let mut hash64_c: u64 = OFFSET;
+    while len >= 24 {
+        hash64_a = (hash64_a ^ read_u64_p(p)).wrapping_mul(MULTIPLIER);
+        hash64_b = (hash64_b ^ read_u64_p(unsafe { p.offset(8) })).wrapping_mul(MULTIPLIER);
+        hash64_c = (hash64_c ^ read_u64_p(unsafe { p.offset(16) })).wrapping_mul(MULTIPLIER);
         len -= 24;
         p = unsafe { p.offset(24) };
     }
 
-    if p == bytes.as_ptr() {
-        hash32_a = (hash32_a ^ rotl32(hash32_c, 5)).wrapping_mul(PRIME);
-    }
-    //Cases 0. .31
+    //Cases 0. .23
     if (len & 16) != 0 {
-        hash32_a = (hash32_a ^ (rotl32(read_u32_p(p), 5) ^ read_u32_p(unsafe { p.offset(4) })))
-            .wrapping_mul(PRIME);
-        hash32_b = (hash32_b
-            ^ (rotl32(read_u32_p(unsafe { p.offset(8) }), 5)
-                ^ read_u32_p(unsafe { p.offset(12) })))
-        .wrapping_mul(PRIME);
+        hash64_a = (hash64_a ^ read_u64_p(p)).wrapping_mul(MULTIPLIER);
+        hash64_b = (hash64_b ^ read_u64_p(unsafe { p.offset(8) })).wrapping_mul(MULTIPLIER);
         p = unsafe { p.offset(16) };
     }
     //Cases 0. .15
     if (len & 8) != 0 {
-        hash32_a = (hash32_a ^ read_u32_p(p)).wrapping_mul(PRIME);
-        hash32_b = (hash32_b ^ read_u32_p(unsafe { p.offset(4) })).wrapping_mul(PRIME);
+        hash64_a = (hash64_a ^ read_u64_p(p)).wrapping_mul(MULTIPLIER);
         p = unsafe { p.offset(8) };
     }
-    //Cases:0. .7
+    //Cases 0. .7
     if (len & 4) != 0 {
-        hash32_a = (hash32_a ^ u32::from(read_u16_p(p))).wrapping_mul(PRIME);
-        hash32_b = (hash32_b ^ u32::from(read_u16_p(unsafe { p.offset(2) }))).wrapping_mul(PRIME);
+        hash64_b = (hash64_b ^ u64::from(read_u32_p(p))).wrapping_mul(MULTIPLIER);
         p = unsafe { p.offset(4) };
     }
-    // //Cases:0. .3
+    //Cases 0. .3
     if (len & 2) != 0 {
-        hash32_a = (hash32_a ^ u32::from(read_u16_p(p))).wrapping_mul(PRIME);
+        hash64_a = (hash64_a ^ u64::from(read_u16_p(p))).wrapping_mul(MULTIPLIER);
         p = unsafe { p.offset(2) };
     }
     if (len & 1) != 0 {
-        hash32_a = (hash32_a ^ u32::from(unsafe { *p })).wrapping_mul(PRIME);
+        hash64_b = (hash64_b ^ u64::from(unsafe { *p })).wrapping_mul(MULTIPLIER);
+    }
+    hash64_a =
+        (hash64_a ^ hash64_b.rotate_left(29) ^ hash64_c.rotate_left(47)).wrapping_mul(MULTIPLIER);
+    hash64_a ^= hash64_a >> 32;
+    hash64_a = hash64_a.wrapping_mul(MULTIPLIER);
+    hash64_a ^ (hash64_a >> 29)
+}
+
+/// Hash function of a `StringHashMap`, see `StringHashMap::set_hash_function`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashFunction {
+    /// `fnv32a_yoshimitsu_triad`
+    #[default]
+    Yoshimitsu,
+    /// `crc32c_hasher`
+    Crc32c,
+}
+
+impl HashFunction {
+    #[inline]
+    pub fn hash(self, seed: u32, bytes: &[u8]) -> u32 {
+        match self {
+            HashFunction::Yoshimitsu => fnv32a_yoshimitsu_triad(seed, bytes),
+            HashFunction::Crc32c => crc32c_hasher(seed, bytes),
+        }
+    }
+
+    /// `hash` of the parts joined with `sep`, without joining them.
+    #[inline]
+    pub fn hash_parts(self, seed: u32, parts: &[&str], sep: &str) -> u32 {
+        let pieces = joined_pieces(parts, sep).map(str::as_bytes);
+        self.hash_pieces(seed, joined_len(parts, sep), pieces)
+    }
+
+    /// `hash` of the concatenation of `pieces`, `len` bytes in total.
+    #[inline]
+    pub(crate) fn hash_pieces<'a>(
+        self,
+        seed: u32,
+        len: usize,
+        pieces: impl Iterator<Item = &'a [u8]>,
+    ) -> u32 {
+        match self {
+            HashFunction::Yoshimitsu => fnv32a_yoshimitsu_triad_pieces(seed, len, pieces),
+            HashFunction::Crc32c => fmix32(!pieces.fold(!seed, crc32c_update)),
+        }
+    }
+}
+
+/// CRC32C (Castagnoli) of `bytes` starting from `seed`, followed by the murmur3 finalizer so the
+/// high bits used to index tables depend on all input bits.
+///
+/// Uses the SSE4.2 `crc32` instruction when the CPU has it, detected at runtime, and a bit-exact
+/// table driven implementation otherwise. CRC is linear: keys colliding under one seed collide
+/// under all seeds, so it is only suited to trusted keys.
+#[inline]
+pub fn crc32c_hasher(seed: u32, bytes: &[u8]) -> u32 {
+    fmix32(!crc32c_update(!seed, bytes))
+}
+
+/// CRC32C of `bytes`, as defined by RFC 3720, without finalizer.
+#[inline]
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !crc32c_update(!0, bytes)
+}
+
+#[inline]
+fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { crc32c_update_sse42(crc, bytes) };
+        }
+    }
+    crc32c_update_software(crc, bytes)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn crc32c_update_sse42(crc: u32, bytes: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+    use std::convert::TryInto;
+    let mut crc = u64::from(crc);
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        crc = _mm_crc32_u64(crc, u64::from_le_bytes(word.try_into().unwrap()));
+    }
+    let mut crc = crc as u32;
+    for &byte in words.remainder() {
+        crc = _mm_crc32_u8(crc, byte);
+    }
+    crc
+}
+
+fn crc32c_update_software(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc = CRC32C_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    // reversed Castagnoli polynomial
+    const POLYNOMIAL: u32 = 0x82F6_3B78;
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[inline]
+fn fmix32(mut hash: u32) -> u32 {
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xC2B2_AE35);
+    hash ^ (hash >> 16)
+}
+
+/// Bytes kept inline by `YoshimitsuHasher` before it allocates.
+const INLINE_LEN: usize = 32;
+
+/// `std::hash::Hasher` computing `fnv32a_yoshimitsu_triad` over all written bytes.
+///
+/// The hash depends on the total length, so the bytes are buffered until `finish`. `impl Hash
+/// for str` writes the bytes followed by a `0xFF` byte, that byte is left out so hashing a
+/// `&str` gives the value of the direct function on its bytes.
+///
+/// `finish` repeats the 32-bit hash in both halves of the `u64`: `hashbrown`, and so
+/// `std::collections::HashMap`, take the control tag from the high bits.
+///
+/// ```
+/// use inohashmap::hasher::{fnv32a_yoshimitsu_triad, YoshimitsuBuildHasher};
+/// use std::collections::HashMap;
+/// use std::hash::BuildHasher;
+///
+/// let build_hasher = YoshimitsuBuildHasher::with_seed(7);
+/// assert_eq!(build_hasher.hash_one("blub") as u32, fnv32a_yoshimitsu_triad(7, b"blub"));
+/// let mut map: HashMap<String, u32, _> = HashMap::with_hasher(build_hasher);
+/// map.insert("blub".to_string(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct YoshimitsuHasher {
+    seed: u32,
+    inline: [u8; INLINE_LEN],
+    len: usize,
+    /// all bytes, once there are more than `INLINE_LEN`
+    spilled: Vec<u8>,
+    num_writes: usize,
+    first_write_len: usize,
+}
+
+impl YoshimitsuHasher {
+    pub fn with_seed(seed: u32) -> Self {
+        YoshimitsuHasher {
+            seed,
+            inline: [0; INLINE_LEN],
+            len: 0,
+            spilled: vec![],
+            num_writes: 0,
+            first_write_len: 0,
+        }
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        if self.len > INLINE_LEN {
+            &self.spilled
+        } else {
+            &self.inline[..self.len]
+        }
+    }
+}
+
+impl Default for YoshimitsuHasher {
+    /// Uses `DEFAULT_SEED`, like `fnv32a_yoshimitsu_hasher`.
+    fn default() -> Self {
+        Self::with_seed(DEFAULT_SEED)
+    }
+}
+
+impl Hasher for YoshimitsuHasher {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        if self.num_writes == 0 {
+            self.first_write_len = bytes.len();
+        }
+        self.num_writes += 1;
+        let new_len = self.len + bytes.len();
+        if new_len <= INLINE_LEN {
+            self.inline[self.len..new_len].copy_from_slice(bytes);
+        } else {
+            if self.len <= INLINE_LEN {
+                self.spilled.extend_from_slice(&self.inline[..self.len]);
+            }
+            self.spilled.extend_from_slice(bytes);
+        }
+        self.len = new_len;
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        let mut bytes = self.bytes();
+        // `impl Hash for str`: the bytes, then `write_u8(0xFF)`
+        if self.num_writes == 2
+            && bytes.len() == self.first_write_len + 1
+            && bytes[self.first_write_len] == 0xFF
+        {
+            bytes = &bytes[..self.first_write_len];
+        }
+        let hash = u64::from(fnv32a_yoshimitsu_triad(self.seed, bytes));
+        hash << 32 | hash
+    }
+}
+
+/// `BuildHasher` creating `YoshimitsuHasher`s with the same seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YoshimitsuBuildHasher {
+    seed: u32,
+}
+
+impl YoshimitsuBuildHasher {
+    /// Fixed seed, use it only for trusted keys, see
+    /// `StringHashMap::with_power_of_two_size_and_seed`.
+    pub fn with_seed(seed: u32) -> Self {
+        YoshimitsuBuildHasher { seed }
+    }
+
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+}
+
+impl Default for YoshimitsuBuildHasher {
+    /// Random seed, like `std::collections::hash_map::RandomState`.
+    fn default() -> Self {
+        Self::with_seed(random_seed())
+    }
+}
+
+impl BuildHasher for YoshimitsuBuildHasher {
+    type Hasher = YoshimitsuHasher;
+
+    #[inline]
+    fn build_hasher(&self) -> YoshimitsuHasher {
+        YoshimitsuHasher::with_seed(self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+    use std::collections::HashSet;
+
+    /// Tokens and pairs of adjacent tokens of the test corpus, without duplicates.
+    fn keys() -> Vec<String> {
+        let contents = read_corpus();
+        let tokens: Vec<&str> = contents.split_whitespace().collect();
+        let pairs = tokens.windows(2).map(|pair| pair.join(" "));
+        let keys: HashSet<String> = tokens
+            .iter()
+            .map(|token| token.to_string())
+            .chain(pairs)
+            .collect();
+        keys.into_iter().collect()
+    }
+
+    fn collisions(hashes: impl Iterator<Item = u64>) -> usize {
+        let mut seen = HashSet::new();
+        hashes.filter(|&hash| !seen.insert(hash)).count()
+    }
+
+    #[test]
+    fn collision_rate_64_bits() {
+        let keys = keys();
+        assert!(keys.len() > 50_000);
+        let hashes64: Vec<u64> = keys
+            .iter()
+            .map(|key| fnv64_yoshimitsu_hasher(key.as_bytes()))
+            .collect();
+        assert_eq!(collisions(hashes64.iter().cloned()), 0);
+
+        // Truncated to 24 bits, both hashes should collide about as often as random values.
+        let expected = (keys.len() * keys.len()) as f64 / f64::from(1 << 25);
+        let low64 = collisions(hashes64.iter().map(|hash| hash & 0xFF_FFFF));
+        let high64 = collisions(hashes64.iter().map(|hash| hash >> 40));
+        let low32 = collisions(
+            keys.iter()
+                .map(|key| u64::from(fnv32a_yoshimitsu_hasher(key.as_bytes()) & 0xFF_FFFF)),
+        );
+        for count in [low64, high64, low32] {
+            assert!(
+                (count as f64) < expected * 1.25,
+                "{} collisions, {} expected",
+                count,
+                expected
+            );
+        }
+    }
+
+    /// `(len, fnv32a_yoshimitsu_hasher, fnv32a_yoshimitsu_triad(1, ..), fnv64_yoshimitsu_hasher,
+    /// fnv64_yoshimitsu_triad(1, ..))` of the first `len` bytes of `test_vector_input()`.
+    const TEST_VECTORS: [(usize, u32, u32, u64, u64); 15] = [
+        (
+            0,
+            0x857DF1C9,
+            0xE59383D7,
+            0xBC3A2F8A0B0CDA43,
+            0xE68E589436D3A727,
+        ),
+        (
+            1,
+            0x18865751,
+            0x1071C6F6,
+            0x2A71D8A2EC556C4F,
+            0x1010DE9FD8DE59DD,
+        ),
+        (
+            2,
+            0xC2B9BE0E,
+            0x843EED99,
+            0x8ADD635DCF5F49CC,
+            0xE31E2BD2A35489B0,
+        ),
+        (
+            3,
+            0x06E10AA5,
+            0x297D3249,
+            0xB4A412B365EC45B7,
+            0x3E36B91196B51B5B,
+        ),
+        (
+            4,
+            0xD1CEF835,
+            0x1ADF80D4,
+            0xFCA43F0B457EA356,
+            0x0DA44048B39F07ED,
+        ),
+        (
+            7,
+            0xC6B2E928,
+            0x00BA97B0,
+            0x0EC278CFB6AE0F3E,
+            0xEC0295D00AA0B00C,
+        ),
+        (
+            8,
+            0x63FCD342,
+            0xAFFDDC93,
+            0xAA509FD3A646E9F3,
+            0x1D3C356B91B4EF26,
+        ),
+        (
+            15,
+            0x804C63CA,
+            0xA0234175,
+            0x465416B2B69D2144,
+            0xFB683107971B2B80,
+        ),
+        (
+            16,
+            0x1ECBFBCE,
+            0xD99438E1,
+            0xAAAEC4D85A11A691,
+            0xB7F5BC730FFEAF9F,
+        ),
+        (
+            23,
+            0x775D724E,
+            0x10863B85,
+            0x4CF12720FC029872,
+            0x56A641D4D2506032,
+        ),
+        (
+            24,
+            0x38F12DAC,
+            0x633F0272,
+            0x5357D55B0186FC13,
+            0xB74CD7AFBEC0E897,
+        ),
+        (
+            25,
+            0x942421F6,
+            0xCD8071E2,
+            0xAD9B14224245D5A8,
+            0x801B19E367C33BE5,
+        ),
+        (
+            47,
+            0xF1C2AA28,
+            0x3B44161E,
+            0xEF51E1EE8DF2880B,
+            0x9153759264FFB2DD,
+        ),
+        (
+            48,
+            0xA96CA0D4,
+            0xAC922DFA,
+            0xE62842D94F995E41,
+            0xE0A1F0F818190C73,
+        ),
+        (
+            100,
+            0x56605EBD,
+            0x5AA31ACE,
+            0x3764242B1ED08550,
+            0xF898EF1EDBEF8CD6,
+        ),
+    ];
+
+    fn test_vector_input() -> Vec<u8> {
+        (0..100u8)
+            .map(|i| i.wrapping_mul(37).wrapping_add(11))
+            .collect()
+    }
+
+    #[test]
+    fn test_vectors() {
+        let input = test_vector_input();
+        for &(len, hash32, seeded32, hash64, seeded64) in TEST_VECTORS.iter() {
+            let bytes = &input[..len];
+            assert_eq!(fnv32a_yoshimitsu_hasher(bytes), hash32, "len {}", len);
+            assert_eq!(fnv32a_yoshimitsu_triad(1, bytes), seeded32, "len {}", len);
+            assert_eq!(fnv64_yoshimitsu_hasher(bytes), hash64, "len {}", len);
+            assert_eq!(fnv64_yoshimitsu_triad(1, bytes), seeded64, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn hash_parts() {
+        let words = [
+            "",
+            "a",
+            "blub",
+            "hello world",
+            "abcdefghijklmnopqrstuvwxyz0123",
+        ];
+        for sep in ["", " ", ":", "--separator--"] {
+            for parts in [
+                &words[..0],
+                &words[..1],
+                &words[1..3],
+                &words[..],
+                &words[2..],
+                &[words[4]; 3][..],
+            ] {
+                let joined = parts.join(sep);
+                for hash_function in [HashFunction::Yoshimitsu, HashFunction::Crc32c] {
+                    assert_eq!(
+                        hash_function.hash_parts(5, parts, sep),
+                        hash_function.hash(5, joined.as_bytes()),
+                        "{:?}",
+                        joined
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crc32c_hardware_and_software() {
+        // check value of the CRC-32/ISCSI catalogue entry
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+        let input = test_vector_input();
+        for len in 0..input.len() {
+            let bytes = &input[..len];
+            assert_eq!(
+                crc32c_update(!1, bytes),
+                crc32c_update_software(!1, bytes),
+                "len {}",
+                len
+            );
+        }
+        assert_ne!(crc32c_hasher(1, b"blub"), crc32c_hasher(2, b"blub"));
+        assert_eq!(
+            HashFunction::Crc32c.hash(1, b"blub"),
+            crc32c_hasher(1, b"blub")
+        );
+    }
+
+    #[test]
+    fn hasher_matches_direct_function() {
+        use std::hash::Hash;
+        let build_hasher = YoshimitsuBuildHasher::with_seed(3);
+        for len in 0..100 {
+            let text: String = (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+            let expected = fnv32a_yoshimitsu_triad(3, text.as_bytes());
+            assert_eq!(build_hasher.hash_one(text.as_str()) as u32, expected);
+            assert_eq!(build_hasher.hash_one(&text) >> 32, u64::from(expected));
+
+            let mut hasher = build_hasher.build_hasher();
+            hasher.write(text.as_bytes());
+            assert_eq!(hasher.finish() as u32, expected);
+        }
+
+        // other types are hashed over all their bytes
+        let mut hasher = YoshimitsuHasher::default();
+        (1u8, "blub").hash(&mut hasher);
+        assert_eq!(
+            hasher.finish() as u32,
+            fnv32a_yoshimitsu_hasher(b"\x01blub\xFF")
+        );
+    }
+
+    #[test]
+    fn std_hash_map() {
+        let mut map: std::collections::HashMap<String, usize, YoshimitsuBuildHasher> =
+            Default::default();
+        let keys = keys();
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(key.clone(), i);
+        }
+        assert_eq!(map.len(), keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map[key], i);
+        }
+    }
+
+    #[test]
+    fn seed_64_bits() {
+        let key = b"blub";
+        assert_ne!(
+            fnv64_yoshimitsu_triad(1, key),
+            fnv64_yoshimitsu_triad(2, key)
+        );
+        for len in 0..64 {
+            let bytes = vec![b'a'; len];
+            let longer = vec![b'a'; len + 1];
+            assert_ne!(
+                fnv64_yoshimitsu_hasher(&bytes),
+                fnv64_yoshimitsu_hasher(&longer)
+            );
+        }
     }
-    hash32_a = (hash32_a ^ rotl32(hash32_b, 5)).wrapping_mul(PRIME);
-    hash32_a ^ (hash32_a >> 16)
 }
@@ -0,0 +1,259 @@
+//! The most frequent strings of a stream, counted in memory bounded by the number of counters.
+
+use crate::bytesref::BytesRef;
+use crate::{power_of_two_for_capacity, StringHashMap};
+
+/// Value in the map of keys that no longer have a counter.
+const EVICTED: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    count: u64,
+    /// the count of the evicted key this counter was taken over from
+    error: u64,
+    key: BytesRef,
+    /// position in `HeavyHitters::heap`
+    heap_pos: u32,
+}
+
+/// A key reported by `HeavyHitters`, its true count is between `count - error` and `count`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeavyHitter<'a> {
+    pub key: &'a str,
+    pub count: u64,
+    pub error: u64,
+}
+
+impl HeavyHitter<'_> {
+    /// The true count is at least this.
+    #[inline]
+    pub fn lower_bound(&self) -> u64 {
+        self.count - self.error
+    }
+}
+
+/// Space-Saving counter, keeping `K` counters for the most frequent keys of a stream.
+///
+/// A key without a counter takes over the counter with the smallest count `min`, it counts
+/// from `min` on with an error of `min`. Every key with more than `total / K` occurrences has a
+/// counter, and keys without a counter occurred at most `min_count` times.
+///
+/// The keys live in a `StringHashMap` pointing to their counter. Keys of evicted counters stay
+/// in the map until there are `K` of them, then the map is compacted and their strings are
+/// dropped from `string_data`, so at most `2 * K` keys are stored.
+///
+/// ```
+/// use inohashmap::HeavyHitters;
+/// let mut heavy_hitters = HeavyHitters::<2>::new();
+/// for text in "a b a c a b".split_whitespace() {
+///     heavy_hitters.insert(text);
+/// }
+/// let top = heavy_hitters.top();
+/// assert_eq!(top[0].key, "a");
+/// assert_eq!((top[0].count, top[0].error), (3, 0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeavyHitters<const K: usize> {
+    /// index of the counter of each key, or `EVICTED`
+    map: StringHashMap<u32>,
+    counters: Vec<Counter>,
+    /// min-heap of counter indices by count
+    heap: Vec<u32>,
+    total: u64,
+}
+
+impl<const K: usize> HeavyHitters<K> {
+    pub fn new() -> Self {
+        assert!(
+            K > 0 && K < EVICTED as usize,
+            "K must be between 1 and u32::MAX - 1"
+        );
+        HeavyHitters {
+            map: StringHashMap::with_power_of_two_size(power_of_two_for_capacity(2 * K)),
+            counters: Vec::with_capacity(K),
+            heap: Vec::with_capacity(K),
+            total: 0,
+        }
+    }
+
+    #[inline]
+    pub fn insert(&mut self, el: &str) {
+        self.add(el, 1);
+    }
+
+    /// Adds `count` occurrences of `el`.
+    pub fn add(&mut self, el: &str, count: u64) {
+        self.total += count;
+        let entry = self.map.get_or_create_entry(el, self.map.hash(el), EVICTED);
+        let index = entry.value;
+        if index != EVICTED {
+            self.counters[index as usize].count += count;
+            self.sift_down(self.counters[index as usize].heap_pos as usize);
+            return;
+        }
+        let key = entry.pointer;
+        if self.counters.len() < K {
+            entry.value = self.counters.len() as u32;
+            self.heap.push(self.counters.len() as u32);
+            self.counters.push(Counter {
+                count,
+                error: 0,
+                key,
+                heap_pos: self.heap.len() as u32 - 1,
+            });
+            self.sift_up(self.heap.len() - 1);
+            return;
+        }
+        // take over the counter with the smallest count
+        let index = self.heap[0];
+        entry.value = index;
+        let counter = &mut self.counters[index as usize];
+        let evicted_key = std::mem::replace(&mut counter.key, key);
+        counter.error = counter.count;
+        counter.count += count;
+        self.sift_down(0);
+        *self.map.get_mut_by_pointer(evicted_key).unwrap() = EVICTED;
+        if self.map.occupied >= 2 * K {
+            self.compact();
+        }
+    }
+
+    /// Total count of all keys added.
+    #[inline]
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Upper bound of the count of keys without a counter, 0 while there are free counters.
+    #[inline]
+    pub fn min_count(&self) -> u64 {
+        if self.counters.len() < K {
+            0
+        } else {
+            self.counters[self.heap[0] as usize].count
+        }
+    }
+
+    /// The count of `el`, if it has a counter.
+    pub fn get(&self, el: &str) -> Option<HeavyHitter<'_>> {
+        match self.map.get(el) {
+            Some(&index) if index != EVICTED => Some(self.heavy_hitter(index)),
+            _ => None,
+        }
+    }
+
+    /// The keys with a counter, by descending count and then by key.
+    pub fn top(&self) -> Vec<HeavyHitter<'_>> {
+        let mut top: Vec<_> = (0..self.counters.len() as u32)
+            .map(|index| self.heavy_hitter(index))
+            .collect();
+        top.sort_unstable_by(|a, b| b.count.cmp(&a.count).then(a.key.cmp(b.key)));
+        top
+    }
+
+    #[inline]
+    fn heavy_hitter(&self, index: u32) -> HeavyHitter<'_> {
+        let counter = &self.counters[index as usize];
+        HeavyHitter {
+            key: self.map.read_string(counter.key),
+            count: counter.count,
+            error: counter.error,
+        }
+    }
+
+    /// Drops the keys of evicted counters from the map.
+    #[cold]
+    fn compact(&mut self) {
+        self.map
+            .retain_in_place(|_, &mut index| index != EVICTED, |_, _| {});
+        for entry in self.map.table.iter().filter(|x| !x.pointer.is_null()) {
+            self.counters[entry.value as usize].key = entry.pointer;
+        }
+    }
+
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if self.count_at(parent) <= self.count_at(pos) {
+                break;
+            }
+            self.swap(pos, parent);
+            pos = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let mut smallest = pos;
+            for child in [2 * pos + 1, 2 * pos + 2] {
+                if child < self.heap.len() && self.count_at(child) < self.count_at(smallest) {
+                    smallest = child;
+                }
+            }
+            if smallest == pos {
+                break;
+            }
+            self.swap(pos, smallest);
+            pos = smallest;
+        }
+    }
+
+    #[inline]
+    fn count_at(&self, pos: usize) -> u64 {
+        self.counters[self.heap[pos] as usize].count
+    }
+
+    #[inline]
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.counters[self.heap[a] as usize].heap_pos = a as u32;
+        self.counters[self.heap[b] as usize].heap_pos = b as u32;
+    }
+}
+
+impl<const K: usize> Default for HeavyHitters<K> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn error_bounds() {
+        let contents = read_corpus();
+        let mut heavy_hitters = HeavyHitters::<100>::new();
+        let mut counts = StringHashMap::<u64>::new();
+        let mut max_string_data = 0;
+        for text in contents.split_whitespace() {
+            heavy_hitters.insert(text);
+            *counts.get_or_create(text, 0) += 1;
+            assert!(heavy_hitters.map.occupied < 200);
+            max_string_data = max_string_data.max(heavy_hitters.map.string_data.len());
+        }
+        assert!(max_string_data < 200 * 20);
+
+        let top = heavy_hitters.top();
+        assert_eq!(top.len(), 100);
+        assert!(top.windows(2).all(|pair| pair[0].count >= pair[1].count));
+        for heavy_hitter in &top {
+            let count = *counts.get(heavy_hitter.key).unwrap();
+            assert!(heavy_hitter.lower_bound() <= count && count <= heavy_hitter.count);
+            assert_eq!(heavy_hitters.get(heavy_hitter.key), Some(*heavy_hitter));
+        }
+        let total = heavy_hitters.total();
+        for (text, &count) in counts.iter() {
+            if count > total / 100 {
+                assert!(heavy_hitters.get(text).is_some());
+            } else if heavy_hitters.get(text).is_none() {
+                assert!(count <= heavy_hitters.min_count());
+            }
+        }
+        assert_eq!(top[0].key, "the");
+        assert_eq!(top[0].error, 0);
+    }
+}
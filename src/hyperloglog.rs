@@ -0,0 +1,184 @@
+//! Estimating the number of distinct keys of a stream, e.g. to size a `StringHashMap` up front.
+//!
+//! # Layout of `to_bytes`
+//!
+//! ```text
+//! magic "INOL" | precision: u8 | 2^precision registers: u8
+//! ```
+
+use crate::frozen::invalid_data;
+use crate::hasher::{fnv64_yoshimitsu_triad, DEFAULT_SEED};
+use crate::power_of_two_for_capacity;
+use std::io;
+
+const MAGIC: &[u8; 4] = b"INOL";
+
+/// HyperLogLog sketch over `fnv64_yoshimitsu_triad` hashes of the keys.
+///
+/// Uses `2^precision` bytes, the standard error of `estimate` is about `1.04 / 2^(precision/2)`,
+/// 1.6% with precision 12. All sketches hash with the same seed, so sketches of the same
+/// precision can be merged, also after `to_bytes` on another machine.
+///
+/// ```
+/// use inohashmap::{HyperLogLog, StringHashMap};
+/// let mut sketch = HyperLogLog::new(12);
+/// for text in "a b a c".split_whitespace() {
+///     sketch.insert(text);
+/// }
+/// assert_eq!(sketch.estimate().round(), 3.0);
+/// let map = StringHashMap::<u32>::with_power_of_two_size(sketch.power_of_two_size());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// `precision` must be between 4 and 18.
+    pub fn new(precision: u8) -> Self {
+        assert!(
+            (4..=18).contains(&precision),
+            "precision must be between 4 and 18"
+        );
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    #[inline]
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    #[inline]
+    pub fn insert(&mut self, el: &str) {
+        let hash = fnv64_yoshimitsu_triad(u64::from(DEFAULT_SEED), el.as_bytes());
+        let index = (hash >> (64 - self.precision)) as usize;
+        // the guard bit bounds the rank by `65 - precision`
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    /// Estimated number of distinct keys inserted.
+    pub fn estimate(&self) -> f64 {
+        let num_registers = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / num_registers),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 1.0 / (1u64 << register) as f64)
+            .sum();
+        let estimate = alpha * num_registers * num_registers / sum;
+        let num_zeros = self
+            .registers
+            .iter()
+            .filter(|&&register| register == 0)
+            .count();
+        if estimate <= 2.5 * num_registers && num_zeros > 0 {
+            // linear counting is more precise for small cardinalities
+            num_registers * (num_registers / num_zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+
+    /// `power_of_two` for `StringHashMap::with_power_of_two_size`, so that the estimated number
+    /// of keys fit without a resize.
+    pub fn power_of_two_size(&self) -> usize {
+        power_of_two_for_capacity(self.estimate().ceil() as usize)
+    }
+
+    /// Adds the keys of `other`, which must have the same precision.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(
+            self.precision, other.precision,
+            "only sketches of the same precision can be merged"
+        );
+        for (register, &other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(other);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MAGIC.len() + 1 + self.registers.len());
+        out.extend_from_slice(MAGIC);
+        out.push(self.precision);
+        out.extend_from_slice(&self.registers);
+        out
+    }
+
+    /// Reads the output of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < MAGIC.len() + 1 || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not a hyperloglog sketch"));
+        }
+        let precision = bytes[4];
+        if !(4..=18).contains(&precision) {
+            return Err(invalid_data("invalid precision"));
+        }
+        let registers = &bytes[5..];
+        if registers.len() != 1 << precision {
+            return Err(invalid_data("length does not match precision"));
+        }
+        if registers.iter().any(|&register| register > 65 - precision) {
+            return Err(invalid_data("invalid register"));
+        }
+        Ok(HyperLogLog {
+            precision,
+            registers: registers.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+    use crate::StringHashMap;
+
+    #[test]
+    fn estimate_merge_and_bytes() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let mut map = StringHashMap::<u32>::new();
+        let mut sketch = HyperLogLog::new(12);
+        let (mut first, mut second) = (HyperLogLog::new(12), HyperLogLog::new(12));
+        for (i, text) in words.iter().enumerate() {
+            map.get_or_create(text, 0);
+            sketch.insert(text);
+            if i < words.len() / 2 {
+                first.insert(text);
+            } else {
+                second.insert(text);
+            }
+        }
+        let error = (sketch.estimate() - map.occupied as f64).abs() / map.occupied as f64;
+        assert!(error < 3.0 * 1.04 / 64.0, "error {}", error);
+        first.merge(&second);
+        assert_eq!(first, sketch);
+        assert!(1usize << (sketch.power_of_two_size() - 1) >= map.occupied);
+
+        let bytes = sketch.to_bytes();
+        assert_eq!(HyperLogLog::from_bytes(&bytes).unwrap(), sketch);
+        assert!(HyperLogLog::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(HyperLogLog::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn small_cardinalities() {
+        let mut sketch = HyperLogLog::new(4);
+        assert_eq!(sketch.estimate(), 0.0);
+        sketch.insert("blub");
+        sketch.insert("blub");
+        assert_eq!(sketch.estimate().round(), 1.0);
+    }
+}
@@ -0,0 +1,130 @@
+//! Keys that are looked up without building the string stored in `string_data`.
+
+use crate::hasher::{joined_len, joined_pieces, HashFunction};
+use vint32::encode_varint_into_writer;
+
+/// A key, as stored in `string_data`. `hash` must give the hash of the stored bytes, so tables
+/// can be rebuilt from `string_data` alone.
+pub(crate) trait Key {
+    fn hash(&self, hash_function: HashFunction, seed: u32) -> u32;
+    /// Number of bytes written by `write_to`.
+    fn len(&self) -> usize;
+    /// Whether `bytes` are the stored bytes of this key.
+    fn eq_bytes(&self, bytes: &[u8]) -> bool;
+    fn write_to(&self, out: &mut Vec<u8>);
+}
+
+impl Key for str {
+    #[inline]
+    fn hash(&self, hash_function: HashFunction, seed: u32) -> u32 {
+        hash_function.hash(seed, self.as_bytes())
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+
+    #[inline]
+    fn eq_bytes(&self, bytes: &[u8]) -> bool {
+        self.as_bytes() == bytes
+    }
+
+    #[inline]
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+/// `parts` joined with `sep`.
+pub(crate) struct Parts<'a> {
+    pub(crate) parts: &'a [&'a str],
+    pub(crate) sep: &'a str,
+}
+
+impl Key for Parts<'_> {
+    #[inline]
+    fn hash(&self, hash_function: HashFunction, seed: u32) -> u32 {
+        hash_function.hash_parts(seed, self.parts, self.sep)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        joined_len(self.parts, self.sep)
+    }
+
+    #[inline]
+    fn eq_bytes(&self, mut bytes: &[u8]) -> bool {
+        if bytes.len() != self.len() {
+            return false;
+        }
+        for piece in joined_pieces(self.parts, self.sep) {
+            let (head, tail) = bytes.split_at(piece.len());
+            if head != piece.as_bytes() {
+                return false;
+            }
+            bytes = tail;
+        }
+        true
+    }
+
+    #[inline]
+    fn write_to(&self, out: &mut Vec<u8>) {
+        for piece in joined_pieces(self.parts, self.sep) {
+            out.extend_from_slice(piece.as_bytes());
+        }
+    }
+}
+
+/// `namespace` as varint, followed by `el`.
+pub(crate) struct Namespaced<'a> {
+    header: [u8; 5],
+    header_len: usize,
+    el: &'a str,
+}
+
+impl<'a> Namespaced<'a> {
+    #[inline]
+    pub(crate) fn new(namespace: u32, el: &'a str) -> Self {
+        let mut header = [0u8; 5];
+        let mut rest = &mut header[..];
+        encode_varint_into_writer(&mut rest, namespace).expect("a varint fits in 5 bytes");
+        let header_len = 5 - rest.len();
+        Namespaced {
+            header,
+            header_len,
+            el,
+        }
+    }
+
+    #[inline]
+    fn header(&self) -> &[u8] {
+        &self.header[..self.header_len]
+    }
+}
+
+impl Key for Namespaced<'_> {
+    #[inline]
+    fn hash(&self, hash_function: HashFunction, seed: u32) -> u32 {
+        let pieces = [self.header(), self.el.as_bytes()];
+        hash_function.hash_pieces(seed, self.len(), pieces.iter().copied())
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.header_len + self.el.len()
+    }
+
+    #[inline]
+    fn eq_bytes(&self, bytes: &[u8]) -> bool {
+        bytes.len() == self.len()
+            && bytes.starts_with(self.header())
+            && &bytes[self.header_len..] == self.el.as_bytes()
+    }
+
+    #[inline]
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.header());
+        out.extend_from_slice(self.el.as_bytes());
+    }
+}
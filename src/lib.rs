@@ -18,14 +18,49 @@ assert_eq!(*val, 2);
 
 */
 
-use crate::bytesref::BytesRef;
-use crate::hasher::fnv32a_yoshimitsu_hasher;
+use crate::key::{Key, Parts};
+use crate::hasher::{fnv32a_yoshimitsu_triad, random_seed, HashFunction};
+use core::cmp::Ordering;
 use core::fmt::Debug;
+use std::collections::BinaryHeap;
 use vint32::{encode_varint_into, decode_varint_slice};
+mod atomic_counter;
+mod bloom;
+mod bounded_counter;
 mod bytesref;
+mod concurrent;
+mod count_min_sketch;
 pub mod hasher;
+mod frozen;
+mod heavy_hitters;
+mod hyperloglog;
+mod key;
+mod namespaced;
+mod parallel;
+mod perfect_hash;
+mod sharded;
+mod snapshot;
+mod term_dictionary;
+#[cfg(feature = "serde")]
+mod serde_impl;
 
-#[derive(Debug)]
+pub use crate::atomic_counter::{AtomicCounter, UnknownKeys};
+pub use crate::bloom::BloomFilter;
+pub use crate::bounded_counter::{BoundedCounter, MIN_MEMORY_BUDGET};
+pub use crate::bytesref::BytesRef;
+pub use crate::concurrent::ConcurrentStringHashMap;
+pub use crate::count_min_sketch::CountMinSketch;
+pub use crate::frozen::{FixedSizeValue, FrozenStringHashMap};
+pub use crate::heavy_hitters::{HeavyHitter, HeavyHitters};
+pub use crate::hyperloglog::HyperLogLog;
+pub use crate::namespaced::NamespacedStringHashMap;
+pub use crate::parallel::{parallel_count, parallel_count_with_seed};
+pub use crate::perfect_hash::{PerfectHashBuilder, PerfectHashMap};
+pub use crate::sharded::ShardedStringHashMap;
+pub use crate::snapshot::{Reader, ReaderFactory, Snapshot, Writer};
+pub use crate::term_dictionary::{PrefixIter, TermDictionary, TermDictionaryWriter};
+
+#[derive(Debug, Clone)]
 pub struct StringHashMap<T> {
     /// contains string in compressed format
     pub(crate) string_data: Vec<u8>,
@@ -34,17 +69,43 @@ pub struct StringHashMap<T> {
     bitshift: usize,
     pub occupied: usize,
     mask: u32,
+    hash_function: HashFunction,
+    /// seed of `hash_function`
+    seed: u32,
+    /// whether `seed` was passed in, rebuilds then derive the new seed from it
+    fixed_seed: bool,
+    max_probe_length: usize,
+    /// number of rebuilds caused by a probe sequence longer than `max_probe_length`
+    rebuilds: usize,
 }
 
+/// Default of `StringHashMap::set_max_probe_length`.
+pub const DEFAULT_MAX_PROBE_LENGTH: usize = 128;
+
 #[derive(Debug, Clone, Copy, Default)]
 pub(crate) struct TableEntry<T> {
-    value: T,
-    pointer: BytesRef,
+    pub(crate) value: T,
+    pub(crate) pointer: BytesRef,
 }
 
 impl<T: Default + Clone + Debug> StringHashMap<T> {
+    /// Creates a map with a random seed, see `with_power_of_two_size_and_seed`.
     #[inline]
     pub fn with_power_of_two_size(power_of_two: usize) -> Self {
+        let mut map = Self::with_power_of_two_size_and_seed(power_of_two, random_seed());
+        map.fixed_seed = false;
+        map
+    }
+
+    /// Creates a map hashing its keys with `fnv32a_yoshimitsu_triad(seed, key)`, see also
+    /// `set_hash_function`.
+    ///
+    /// The seed decides where keys end up in the table, with a fixed seed the iteration order
+    /// only depends on the inserted keys. Keys chosen by someone knowing the seed can all collide,
+    /// so use a fixed seed only for trusted input. `new` and `with_power_of_two_size` draw a
+    /// random seed per map.
+    #[inline]
+    pub fn with_power_of_two_size_and_seed(power_of_two: usize, seed: u32) -> Self {
         let shift = power_of_two - 1;
         let mut table = vec![];
         table.resize(1 << shift, TableEntry::default());
@@ -54,6 +115,11 @@ impl<T: Default + Clone + Debug> StringHashMap<T> {
             table,
             bitshift: 32 - power_of_two,
             occupied: 0,
+            hash_function: HashFunction::default(),
+            seed,
+            fixed_seed: true,
+            max_probe_length: DEFAULT_MAX_PROBE_LENGTH,
+            rebuilds: 0,
         }
     }
     #[inline]
@@ -61,40 +127,232 @@ impl<T: Default + Clone + Debug> StringHashMap<T> {
         Self::with_power_of_two_size(10)
     }
 
+    /// `new` with a fixed seed, see `with_power_of_two_size_and_seed`.
+    #[inline]
+    pub fn with_seed(seed: u32) -> Self {
+        Self::with_power_of_two_size_and_seed(10, seed)
+    }
+
+    /// The current seed, it changes when the table is rebuilt.
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Rehashes all keys with `hash_function`, `HashFunction::Yoshimitsu` by default.
+    ///
+    /// `HashFunction::Crc32c` is only suited to trusted keys: keys of the same length colliding
+    /// under one seed collide under all seeds, so a new seed doesn't help against them. A map
+    /// hashing with it switches to `HashFunction::Yoshimitsu` when a probe sequence gets too
+    /// long, see `set_max_probe_length`.
+    pub fn set_hash_function(&mut self, hash_function: HashFunction) {
+        if hash_function != self.hash_function {
+            self.hash_function = hash_function;
+            self.rebuild(self.table.len());
+        }
+    }
+
+    /// The hash function of the map, see `set_hash_function`.
+    #[inline]
+    pub fn hash_function(&self) -> HashFunction {
+        self.hash_function
+    }
+
+    /// When inserting needs more than `max_probe_length` probes, the table is rebuilt in place
+    /// with a new seed. Such probe sequences are very unlikely with random keys, they are
+    /// a sign of keys crafted to collide. `usize::MAX` disables the check.
+    ///
+    /// The new seed is random, unless the map was created with a fixed seed. Then it is derived
+    /// from the old seed, so the same keys still give the same map. A map hashing with
+    /// `HashFunction::Crc32c` is rebuilt with `HashFunction::Yoshimitsu`.
+    #[inline]
+    pub fn set_max_probe_length(&mut self, max_probe_length: usize) {
+        self.max_probe_length = max_probe_length;
+    }
+
+    /// How many times the table was rebuilt because of a too long probe sequence, see
+    /// `set_max_probe_length`. Poll it to alert on hash flooding.
+    #[inline]
+    pub fn rebuilds(&self) -> usize {
+        self.rebuilds
+    }
+
+    /// Hash of `el` used by this map.
+    #[inline]
+    pub(crate) fn hash(&self, el: &str) -> u32 {
+        self.hash_bytes(el.as_bytes())
+    }
+
+    /// Hash of the stored bytes `key` of a key.
+    #[inline]
+    fn hash_bytes(&self, key: &[u8]) -> u32 {
+        self.hash_function.hash(self.seed, key)
+    }
+
     #[inline]
     pub fn get_or_create(&mut self, el: &str, value: T) -> &mut T {
+        &mut self.get_or_create_entry(el, self.hash(el), value).value
+    }
+
+    /// `get_or_create` with `hash = fnv32a_yoshimitsu_triad(seed, el)` already computed. The
+    /// hash is computed again if the map no longer hashes with it.
+    #[inline]
+    pub(crate) fn get_or_create_with_hash(
+        &mut self,
+        el: &str,
+        seed: u32,
+        hash: u32,
+        value: T,
+    ) -> &mut T {
+        let hash = if seed == self.seed && self.hash_function == HashFunction::Yoshimitsu {
+            hash
+        } else {
+            self.hash(el)
+        };
+        &mut self.get_or_create_entry(el, hash, value).value
+    }
+
+    /// `get_or_create` with `self.hash(el)` already computed, returning the whole entry.
+    #[inline]
+    pub(crate) fn get_or_create_entry<K: Key + ?Sized>(
+        &mut self,
+        el: &K,
+        hash: u32,
+        value: T,
+    ) -> &mut TableEntry<T> {
+        let (slot, _) = self.get_or_create_slot(el, hash, value);
+        self.get_entry_mut(slot)
+    }
+
+    /// Returns the slot of `el`, inserted with `value` if it is new, and whether it was inserted.
+    #[inline]
+    fn get_or_create_slot<K: Key + ?Sized>(
+        &mut self,
+        el: &K,
+        hash: u32,
+        value: T,
+    ) -> (usize, bool) {
         // check load factor, resize when 0.5
         // if self.occupied as f32 * 1.5 > self.table.len() as f32 {
         if self.occupied as f32 * 1.5 > self.table.len() as f32 {
             self.resize();
         }
-        let mut probe = self.get_probe(el);
-        let mut hash = probe.next_probe() as usize;
+        let (mut slot, mut found, probe_length) = self.find_slot(el, hash);
+        if probe_length > self.max_probe_length {
+            self.rebuild_with_new_seed();
+            (slot, found, _) = self.find_slot(el, el.hash(self.hash_function, self.seed));
+        }
+        if found {
+            return (slot, false);
+        }
+        self.occupied += 1;
+        self.put_in_bucket(slot, el, value);
+        (slot, true)
+    }
 
+    /// Returns the slot of `el` or the empty slot where it belongs, whether `el` was found, and
+    /// the number of probes.
+    #[inline]
+    fn find_slot<K: Key + ?Sized>(&self, el: &K, hash: u32) -> (usize, bool, usize) {
+        let mut probe = self.get_probe_with_hash(hash);
+        let mut probe_length = 1;
         loop {
-            let entry = self.get_entry(hash);
+            let slot = probe.next_probe() as usize;
+            let entry = self.get_entry(slot);
             if entry.pointer.is_null() {
-                self.occupied += 1;
-                let inserted_value = self.put_in_bucket(hash as usize, el, value);
-                return &mut inserted_value.value;
+                return (slot, false, probe_length);
+            } else if el.eq_bytes(self.read_key_bytes(entry.pointer)) {
+                return (slot, true, probe_length);
+            }
+            probe_length += 1;
+        }
+    }
+
+    /// `get_or_create` for the key `parts.join(sep)`. The parts are hashed and compared one
+    /// after another, the joined key is only built in `string_data` when it is inserted.
+    ///
+    /// ```
+    /// use inohashmap::StringHashMap;
+    /// let mut map = StringHashMap::<u32>::new();
+    /// *map.get_or_create_parts(&["new", "york"], " ", 0) += 1;
+    /// assert_eq!(*map.get_or_create("new york", 0), 1);
+    /// ```
+    #[inline]
+    pub fn get_or_create_parts(&mut self, parts: &[&str], sep: &str, value: T) -> &mut T {
+        self.get_or_create_key(&Parts { parts, sep }, value).0
+    }
+
+    /// `get_or_create` for any `Key`, also returns whether the key was inserted.
+    #[inline]
+    pub(crate) fn get_or_create_key<K: Key + ?Sized>(
+        &mut self,
+        key: &K,
+        value: T,
+    ) -> (&mut T, bool) {
+        let hash = key.hash(self.hash_function, self.seed);
+        let (slot, inserted) = self.get_or_create_slot(key, hash, value);
+        (&mut self.get_entry_mut(slot).value, inserted)
+    }
+
+    /// Returns the value of `el` without inserting it.
+    #[inline]
+    pub(crate) fn get(&self, el: &str) -> Option<&T> {
+        let mut probe = self.get_probe(el.as_bytes());
+        loop {
+            let entry = self.get_entry(probe.next_probe() as usize);
+            if entry.pointer.is_null() {
+                return None;
             } else if self.read_string(entry.pointer) == el {
-                return &mut self.get_entry_mut(hash as usize).value;
+                return Some(&entry.value);
             }
-            hash = probe.next_probe() as usize;
         }
     }
 
+    /// Returns the value of `el` mutably without inserting it.
     #[inline]
-    fn get_probe(&self, el: &str) -> QuadraticProbing {
-        let hash = fnv32a_yoshimitsu_hasher(el.as_bytes());
+    pub(crate) fn get_mut(&mut self, el: &str) -> Option<&mut T> {
+        let (slot, found, _) = self.find_slot(el, self.hash(el));
+        if found {
+            Some(&mut self.get_entry_mut(slot).value)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of the key stored at `pointer` in `string_data`.
+    #[inline]
+    pub(crate) fn get_mut_by_pointer(&mut self, pointer: BytesRef) -> Option<&mut T> {
+        let mut probe = self.get_probe(self.read_key_bytes(pointer));
+        loop {
+            let slot = probe.next_probe() as usize;
+            let entry_pointer = self.get_entry(slot).pointer;
+            if entry_pointer.addr() == pointer.addr() {
+                return Some(&mut self.get_entry_mut(slot).value);
+            } else if entry_pointer.is_null() {
+                return None;
+            }
+        }
+    }
+
+    #[inline]
+    fn get_probe(&self, key: &[u8]) -> QuadraticProbing {
+        self.get_probe_with_hash(self.hash_bytes(key))
+    }
+
+    #[inline]
+    fn get_probe_with_hash(&self, hash: u32) -> QuadraticProbing {
         let hash = hash >> self.bitshift;
-        let probe = QuadraticProbing::compute(hash, self.mask);
-        probe
+        QuadraticProbing::compute(hash, self.mask)
     }
 
     #[inline]
-    fn put_entry_resize(&mut self, el: &str, new_entry: TableEntry<T>) {
-        let mut probe = self.get_probe(el);
+    fn put_entry_resize(&mut self, key: &[u8], new_entry: TableEntry<T>) {
+        self.put_entry_with_hash(self.hash_bytes(key), new_entry);
+    }
+
+    #[inline]
+    fn put_entry_with_hash(&mut self, hash: u32, new_entry: TableEntry<T>) {
+        let mut probe = self.get_probe_with_hash(hash);
         let mut hash = probe.next_probe();
         loop {
             let entry = self.get_entry_mut(hash as usize);
@@ -115,40 +373,323 @@ impl<T: Default + Clone + Debug> StringHashMap<T> {
             .map(|entry| &entry.value)
     }
 
+    /// Iterates over all keys and their values, in table order.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.table
+            .iter()
+            .filter(|entry| !entry.pointer.is_null())
+            .map(move |entry| (self.read_string(entry.pointer), &entry.value))
+    }
+
+    /// The `k` entries with the largest `key_fn(value)`, in descending order, equal ones ordered
+    /// by key. Runs in one pass over the table, keeping the best `k` entries in a binary heap.
+    ///
+    /// ```
+    /// use inohashmap::StringHashMap;
+    /// let mut map = StringHashMap::<u32>::new();
+    /// for text in "a b a c a b".split_whitespace() {
+    ///     *map.get_or_create(text, 0) += 1;
+    /// }
+    /// assert_eq!(map.top_k(2, |&count| count), vec![("a", &3), ("b", &2)]);
+    /// ```
+    pub fn top_k<O: Ord>(&self, k: usize, mut key_fn: impl FnMut(&T) -> O) -> Vec<(&str, &T)> {
+        if k == 0 {
+            return vec![];
+        }
+        // the top of the heap is the lowest ranked of the best `k` entries seen so far
+        let mut heap = BinaryHeap::with_capacity(k.min(self.occupied));
+        for entry in self.table.iter().filter(|entry| !entry.pointer.is_null()) {
+            let ranked = Ranked {
+                order: key_fn(&entry.value),
+                pointer: entry.pointer,
+                string_data: &self.string_data,
+                value: &entry.value,
+            };
+            if heap.len() < k {
+                heap.push(ranked);
+            } else if let Some(mut lowest) = heap.peek_mut() {
+                if ranked < *lowest {
+                    *lowest = ranked;
+                }
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|ranked| (ranked.key(), ranked.value))
+            .collect()
+    }
+
     #[inline]
     fn get_entry(&self, hash: usize) -> &TableEntry<T> {
         unsafe { self.table.get_unchecked(hash) }
     }
     #[inline]
     fn get_entry_mut(&mut self, hash: usize) -> &mut TableEntry<T> {
-        unsafe { self.table.get_unchecked_mut(hash as usize) }
+        unsafe { self.table.get_unchecked_mut(hash) }
     }
 
     /// Doubles the size of the table
     #[cold]
     pub fn resize(&mut self) {
+        self.bitshift -= 1;
+        self.rebuild(self.table.len() * 2);
+    }
+
+    #[cold]
+    fn rebuild_with_new_seed(&mut self) {
+        // CRC32C collisions don't depend on the seed
+        self.hash_function = HashFunction::Yoshimitsu;
+        self.rebuilds += 1;
+        self.seed = if self.fixed_seed {
+            fnv32a_yoshimitsu_triad(self.seed, &(self.rebuilds as u64).to_le_bytes())
+        } else {
+            random_seed()
+        };
+        self.rebuild(self.table.len());
+    }
+
+    /// Reinserts all entries into a table of `num_slots`, `bitshift` and `seed` must be set.
+    fn rebuild(&mut self, num_slots: usize) {
         let mut table: Vec<TableEntry<T>> = vec![];
-        table.resize(self.table.len() * 2, TableEntry::default());
+        table.resize(num_slots, TableEntry::default());
         self.mask = table.len() as u32 - 1;
 
         std::mem::swap(&mut self.table, &mut table);
-        self.bitshift -= 1;
         for entry in table.into_iter().filter(|x| !x.pointer.is_null()) {
-            let text = self.read_string(entry.pointer);
-            // casting away lifetime of text
+            let key = self.read_key_bytes(entry.pointer);
+            // casting away lifetime of key
             // Since string_data will not be altered in put_entry_resize
-            let text = unsafe { std::mem::transmute::<&str, &'static str>(text) };
-            self.put_entry_resize(text, entry);
+            let key = unsafe { std::mem::transmute::<&[u8], &'static [u8]>(key) };
+            self.put_entry_resize(key, entry);
         }
     }
 
+    /// Keeps only the entries for which `keep(key, value)` returns true, calling `keep` in the
+    /// order the keys were inserted. The table is rebuilt and the strings of the removed keys
+    /// are dropped from `string_data`, then both are shrunk, see `shrink_to_fit`.
+    ///
+    /// ```
+    /// use inohashmap::StringHashMap;
+    /// let mut map = StringHashMap::<u32>::new();
+    /// for text in "a b a c a b".split_whitespace() {
+    ///     *map.get_or_create(text, 0) += 1;
+    /// }
+    /// map.retain(|_, &mut count| count >= 2);
+    /// assert_eq!(map.top_k(3, |&count| count), vec![("a", &3), ("b", &2)]);
+    /// ```
+    pub fn retain(&mut self, keep: impl FnMut(&str, &mut T) -> bool) {
+        self.retain_in_place(keep, |_, _| {});
+        self.shrink_to_fit();
+    }
+
+    /// `retain`, returning the old and the new handle of each kept key, in ascending order of
+    /// the old handle. Use it to update handles stored outside of the map.
+    pub fn retain_with_mapping(
+        &mut self,
+        keep: impl FnMut(&str, &mut T) -> bool,
+    ) -> Vec<(BytesRef, BytesRef)> {
+        let mut mapping = vec![];
+        self.retain_in_place(keep, |old, new| mapping.push((old, new)));
+        self.shrink_to_fit();
+        mapping
+    }
+
+    /// Converts the values with `f(key, value)`, in table order. The keys stay where they are in
+    /// the table and in `string_data`, so nothing is rehashed or copied.
+    ///
+    /// ```
+    /// use inohashmap::StringHashMap;
+    /// let mut counts = StringHashMap::<u32>::new();
+    /// *counts.get_or_create("blub", 0) += 2;
+    /// let mut frequencies = counts.map_values(|_, count| count as f32 / 2.0);
+    /// assert_eq!(*frequencies.get_or_create("blub", 0.0), 1.0);
+    /// ```
+    pub fn map_values<U: Default>(self, mut f: impl FnMut(&str, T) -> U) -> StringHashMap<U> {
+        let StringHashMap {
+            string_data,
+            table,
+            bitshift,
+            occupied,
+            mask,
+            hash_function,
+            seed,
+            fixed_seed,
+            max_probe_length,
+            rebuilds,
+        } = self;
+        let table = table
+            .into_iter()
+            .map(|entry| TableEntry {
+                value: if entry.pointer.is_null() {
+                    U::default()
+                } else {
+                    f(read_string_from(&string_data, entry.pointer), entry.value)
+                },
+                pointer: entry.pointer,
+            })
+            .collect();
+        StringHashMap {
+            string_data,
+            table,
+            bitshift,
+            occupied,
+            mask,
+            hash_function,
+            seed,
+            fixed_seed,
+            max_probe_length,
+            rebuilds,
+        }
+    }
+
+    /// Handle of `el`, the position of its string in `string_data`. It stays the same until
+    /// keys are removed with `retain`.
     #[inline]
-    pub(crate) fn put_in_bucket(&mut self, hash: usize, el: &str, value: T) -> &mut TableEntry<T> {
+    pub fn handle(&self, el: &str) -> Option<BytesRef> {
+        let (slot, found, _) = self.find_slot(el, self.hash(el));
+        if found {
+            Some(self.get_entry(slot).pointer)
+        } else {
+            None
+        }
+    }
+
+    /// Shrinks the table to the size `with_power_of_two_size` would have for the number of
+    /// keys, and the capacity of `string_data` to its length.
+    pub fn shrink_to_fit(&mut self) {
+        let power_of_two = power_of_two_for_capacity(self.occupied);
+        let num_slots = 1 << (power_of_two - 1);
+        if num_slots < self.table.len() {
+            self.bitshift = 32 - power_of_two;
+            self.rebuild(num_slots);
+        }
+        self.string_data.shrink_to_fit();
+    }
+
+    /// `retain` without shrinking. The strings of the kept keys are moved to the front of
+    /// `string_data`, `moved(old, new)` is called for each of them.
+    pub(crate) fn retain_in_place(
+        &mut self,
+        mut keep: impl FnMut(&str, &mut T) -> bool,
+        mut moved: impl FnMut(BytesRef, BytesRef),
+    ) {
+        let mut old_table = vec![TableEntry::default(); self.table.len()];
+        std::mem::swap(&mut self.table, &mut old_table);
+        let mut read = 0;
+        let mut write = 0;
+        while read < self.string_data.len() {
+            let mut pos = read;
+            let length_string = decode_varint_slice(&self.string_data, &mut pos).unwrap();
+            let end = pos + length_string as usize;
+            let hash = self.hash_bytes(&self.string_data[pos..end]);
+            // the entry of the key is the one pointing to `read`
+            let mut probe = self.get_probe_with_hash(hash);
+            let slot = loop {
+                let slot = probe.next_probe() as usize;
+                let pointer = old_table[slot].pointer;
+                if pointer.addr() as usize == read {
+                    break Some(slot);
+                } else if pointer.is_null() {
+                    break None;
+                }
+            };
+            if let Some(slot) = slot {
+                let mut value = std::mem::take(&mut old_table[slot].value);
+                let key = read_string_from(&self.string_data, BytesRef(read as u32));
+                if keep(key, &mut value) {
+                    self.string_data.copy_within(read..end, write);
+                    let pointer = BytesRef(write as u32);
+                    moved(BytesRef(read as u32), pointer);
+                    self.put_entry_with_hash(hash, TableEntry { value, pointer });
+                    write += end - read;
+                } else {
+                    self.occupied -= 1;
+                }
+            }
+            read = end;
+        }
+        self.string_data.truncate(write);
+    }
+
+    /// Moves all entries of `other` into this map. Values of keys present in both maps are
+    /// combined with `combine(&mut value_in_self, value_in_other)`.
+    ///
+    /// The table is sized once for both maps and the strings of new keys are copied from `other`
+    /// in contiguous runs.
+    pub fn merge_from(&mut self, other: StringHashMap<T>, mut combine: impl FnMut(&mut T, T)) {
+        let num_entries = self.occupied + other.occupied;
+        if num_entries as f32 * 1.5 > self.table.len() as f32 {
+            let power_of_two = power_of_two_for_capacity(num_entries);
+            self.bitshift = 32 - power_of_two;
+            self.rebuild(1 << (power_of_two - 1));
+        }
+
+        let StringHashMap {
+            string_data: other_data,
+            table: other_table,
+            ..
+        } = other;
+        // New keys claim their slot right away, pointing past the end of `string_data` as if
+        // `other_data` was appended. Keys of `other` are distinct, so they are never compared
+        // against such pending slots.
+        let base = self.string_data.len();
+        let mut new_slots = vec![];
+        for entry in other_table.into_iter().filter(|x| !x.pointer.is_null()) {
+            let key = read_key_bytes_from(&other_data, entry.pointer);
+            let mut probe = self.get_probe(key);
+            loop {
+                let hash = probe.next_probe() as usize;
+                let pointer = self.get_entry(hash).pointer;
+                if pointer.is_null() {
+                    *self.get_entry_mut(hash) = TableEntry {
+                        value: entry.value,
+                        pointer: BytesRef(base as u32 + entry.pointer.addr()),
+                    };
+                    new_slots.push(hash);
+                    break;
+                } else if (pointer.addr() as usize) < base && self.read_key_bytes(pointer) == key {
+                    combine(&mut self.get_entry_mut(hash).value, entry.value);
+                    break;
+                }
+            }
+        }
+        self.occupied += new_slots.len();
+
+        // copy the strings of new keys in runs of adjacent strings in `other_data`
+        new_slots.sort_unstable_by_key(|&hash| self.get_entry(hash).pointer.addr());
+        let mut run_start = 0;
+        let mut run_end = 0;
+        let mut run_target = base;
+        for hash in new_slots {
+            let start = self.get_entry(hash).pointer.addr() as usize - base;
+            if start != run_end {
+                self.string_data
+                    .extend_from_slice(&other_data[run_start..run_end]);
+                run_start = start;
+                run_target = self.string_data.len();
+            }
+            let mut pos = start;
+            let length_string = decode_varint_slice(&other_data, &mut pos).unwrap();
+            run_end = pos + length_string as usize;
+            self.get_entry_mut(hash).pointer = BytesRef((run_target + start - run_start) as u32);
+        }
+        self.string_data
+            .extend_from_slice(&other_data[run_start..run_end]);
+    }
+
+    #[inline]
+    pub(crate) fn put_in_bucket<K: Key + ?Sized>(
+        &mut self,
+        hash: usize,
+        el: &K,
+        value: T,
+    ) -> &mut TableEntry<T> {
         let pos = BytesRef(self.string_data.len() as u32);
 
         encode_varint_into(&mut self.string_data, el.len() as u32);    
 
-        self.string_data.extend_from_slice(el.as_bytes());
+        el.write_to(&mut self.string_data);
         // unsafe {
         //     self.string_data.reserve(el.len());
         //     let target = self.string_data.as_mut_ptr().add(self.string_data.len());
@@ -166,16 +707,108 @@ impl<T: Default + Clone + Debug> StringHashMap<T> {
 
     #[inline]
     pub(crate) fn read_string(&self, pos: BytesRef) -> &str {
-        let mut pos = pos.addr() as usize;
-        let length_string = decode_varint_slice(&self.string_data, &mut pos).unwrap();
-        unsafe {
-            std::str::from_utf8_unchecked(
-                &self.string_data.get_unchecked(pos..pos + length_string as usize),
-            )
-        }
+        read_string_from(&self.string_data, pos)
+    }
+
+    #[inline]
+    pub(crate) fn read_key_bytes(&self, pos: BytesRef) -> &[u8] {
+        read_key_bytes_from(&self.string_data, pos)
+    }
+}
+
+/// Smallest `power_of_two` for `with_power_of_two_size`, so that `num_entries` fit without a resize.
+pub(crate) fn power_of_two_for_capacity(num_entries: usize) -> usize {
+    let mut power_of_two = 10;
+    while num_entries as f32 * 1.5 > (1usize << (power_of_two - 1)) as f32 {
+        power_of_two += 1;
+    }
+    power_of_two
+}
+
+/// Key at `pos`, which must have been stored as a `&str`.
+#[inline]
+pub(crate) fn read_string_from(string_data: &[u8], pos: BytesRef) -> &str {
+    unsafe { std::str::from_utf8_unchecked(read_key_bytes_from(string_data, pos)) }
+}
+
+/// Stored bytes of the key at `pos`.
+#[inline]
+pub(crate) fn read_key_bytes_from(string_data: &[u8], pos: BytesRef) -> &[u8] {
+    let mut pos = pos.addr() as usize;
+    let length_string = decode_varint_slice(string_data, &mut pos).unwrap();
+    unsafe { string_data.get_unchecked(pos..pos + length_string as usize) }
+}
+
+impl<T: Default + Clone + Debug> Default for StringHashMap<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like `StringHashMap::read_string`, but checks the varint header and bounds of untrusted data.
+pub(crate) fn read_key_bytes_checked(string_data: &[u8], addr: u32) -> Option<&[u8]> {
+    let mut pos = addr as usize;
+    let length_string = decode_varint_checked(string_data, &mut pos)? as usize;
+    string_data.get(pos..pos.checked_add(length_string)?)
+}
+
+/// `decode_varint_slice`, returning `None` instead of panicking on truncated input.
+pub(crate) fn decode_varint_checked(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let header = data.get(*pos..)?;
+    let header_len = header
+        .iter()
+        .take(4)
+        .position(|byte| byte & 128 == 0)
+        .map_or(5, |i| i + 1);
+    if header.len() < header_len {
+        return None;
+    }
+    decode_varint_slice(data, pos)
+}
+
+/// Entry of the heap in `StringHashMap::top_k`. Greater is ranked lower: a smaller `order`,
+/// then a greater key.
+struct Ranked<'a, O, T> {
+    order: O,
+    pointer: BytesRef,
+    string_data: &'a [u8],
+    value: &'a T,
+}
+
+impl<'a, O, T> Ranked<'a, O, T> {
+    #[inline]
+    fn key(&self) -> &'a str {
+        read_string_from(self.string_data, self.pointer)
+    }
+}
+
+impl<O: Ord, T> Ord for Ranked<'_, O, T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .order
+            .cmp(&self.order)
+            .then_with(|| self.key().cmp(other.key()))
+    }
+}
+
+impl<O: Ord, T> PartialOrd for Ranked<'_, O, T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<O: Ord, T> PartialEq for Ranked<'_, O, T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl<O: Ord, T> Eq for Ranked<'_, O, T> {}
+
 struct QuadraticProbing {
     hash: u32,
     i: u32,
@@ -191,11 +824,21 @@ impl QuadraticProbing {
     #[inline]
     fn next_probe(&mut self) -> u32 {
         self.i += 1;
-        (self.hash + (self.i + self.i * self.i) >> 1) & self.mask
+        (self
+            .hash
+            .wrapping_add(self.i.wrapping_add(self.i.wrapping_mul(self.i)))
+            >> 1)
+            & self.mask
         // (self.hash + (self.i * self.i)) & self.mask
     }
 }
 
+/// The test corpus, Pride and Prejudice.
+#[cfg(test)]
+pub(crate) fn read_corpus() -> String {
+    std::fs::read_to_string("1342-0.txt").unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,7 +862,7 @@ mod tests {
 
         let sum: u32 = map.get_values().sum();
         assert_eq!(sum, counter);
-        assert_eq!(map.string_data.len() < 1_000_000, true);
+        assert!(map.string_data.len() < 1_000_000);
 
         dbg!(counter);
 
@@ -232,6 +875,274 @@ mod tests {
         // dbg!(map.existing);
 
     }
+    #[test]
+    fn top_k() {
+        let contents = read_corpus();
+        let mut map = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *map.get_or_create(text, 0) += 1;
+        }
+
+        let mut expected: Vec<_> = map.iter().collect();
+        expected.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        // many words occur once, so ties are broken by key
+        for &k in &[0, 1, 10, 5000, map.occupied, map.occupied + 1, usize::MAX] {
+            let top = map.top_k(k, |&count| count);
+            assert_eq!(top, expected[..k.min(map.occupied)]);
+        }
+        let shortest = map.top_k(3, |&count| std::cmp::Reverse(count));
+        assert!(shortest.iter().all(|&(_, &count)| count == 1));
+    }
+
+    #[test]
+    fn retain() {
+        let contents = read_corpus();
+        let mut map = StringHashMap::<u32>::new();
+        let mut expected = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *map.get_or_create(text, 0) += 1;
+        }
+        let old_handles: Vec<_> = map
+            .iter()
+            .map(|(text, _)| (map.handle(text).unwrap(), text.to_string()))
+            .collect();
+        let mut visited = vec![];
+        let mapping = map.retain_with_mapping(|text, &mut count| {
+            visited.push(text.to_string());
+            count >= 3
+        });
+        for text in contents.split_whitespace() {
+            if map.get(text).is_some() {
+                *expected.get_or_create(text, 0) += 1;
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        let inserted: Vec<_> = contents
+            .split_whitespace()
+            .filter(|text| seen.insert(*text))
+            .collect();
+        assert_eq!(visited, inserted);
+
+        assert_eq!(map.occupied, expected.occupied);
+        assert!(map
+            .iter()
+            .all(|(text, &count)| count >= 3 && expected.get(text) == Some(&count)));
+        assert_eq!(map.string_data, expected.string_data);
+        assert_eq!(map.string_data.capacity(), map.string_data.len());
+        assert_eq!(
+            map.table.len(),
+            1 << (power_of_two_for_capacity(map.occupied) - 1)
+        );
+
+        assert_eq!(mapping.len(), map.occupied);
+        assert!(mapping
+            .windows(2)
+            .all(|pair| pair[0].0.addr() < pair[1].0.addr()));
+        let old_keys: std::collections::HashMap<_, _> = old_handles
+            .iter()
+            .map(|(handle, text)| (handle.addr(), text.as_str()))
+            .collect();
+        for &(old, new) in &mapping {
+            let text = old_keys[&old.addr()];
+            assert_eq!(map.handle(text).unwrap().addr(), new.addr());
+        }
+        for (handle, text) in &old_handles {
+            let kept = mapping.binary_search_by_key(&handle.addr(), |(old, _)| old.addr());
+            assert_eq!(kept.is_ok(), map.handle(text).is_some());
+        }
+
+        map.retain(|_, _| false);
+        assert_eq!(map.occupied, 0);
+        assert!(map.string_data.is_empty());
+        assert_eq!(map.get("the"), None);
+    }
+
+    #[test]
+    fn map_values() {
+        let contents = read_corpus();
+        let mut counts = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *counts.get_or_create(text, 0) += 1;
+        }
+
+        let expected = counts.clone();
+        let mut infos = counts.map_values(|text, count| (count, text.len()));
+        assert_eq!(infos.string_data, expected.string_data);
+        assert_eq!(infos.occupied, expected.occupied);
+        assert_eq!((infos.mask, infos.bitshift), (expected.mask, expected.bitshift));
+        for (entry, expected) in infos.table.iter().zip(&expected.table) {
+            assert_eq!(entry.pointer.addr(), expected.pointer.addr());
+        }
+        for (text, &count) in expected.iter() {
+            assert_eq!(*infos.get_or_create(text, (0, 0)), (count, text.len()));
+        }
+        assert_eq!(infos.occupied, expected.occupied);
+    }
+
+    #[test]
+    fn merge_from() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let (first, second) = words.split_at(words.len() / 2);
+
+        let mut expected = StringHashMap::<u32>::new();
+        let mut map = StringHashMap::<u32>::new();
+        let mut other = StringHashMap::<u32>::with_power_of_two_size(1);
+        for text in first {
+            *expected.get_or_create(text, 0) += 1;
+            *map.get_or_create(text, 0) += 1;
+        }
+        for text in second {
+            *expected.get_or_create(text, 0) += 1;
+            *other.get_or_create(text, 0) += 1;
+        }
+
+        map.merge_from(other, |value, other_value| *value += other_value);
+        assert_eq!(map.occupied, expected.occupied);
+        assert_eq!(map.string_data.len(), expected.string_data.len());
+        for (text, value) in expected.iter() {
+            assert_eq!(map.get_or_create(text, 0), value);
+        }
+        assert_eq!(map.occupied, expected.occupied);
+    }
+    #[test]
+    fn merge_from_empty() {
+        let mut map = StringHashMap::<u32>::new();
+        let mut other = StringHashMap::<u32>::new();
+        other.get_or_create("blub", 1);
+        map.merge_from(other, |_, _| panic!("no shared keys"));
+        map.merge_from(StringHashMap::new(), |_, _| panic!("no shared keys"));
+        assert_eq!(map.get_or_create("blub", 0), &1);
+        assert_eq!(map.occupied, 1);
+    }
+    #[test]
+    fn get_or_create_parts() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let mut joined = StringHashMap::<u32>::new();
+        let mut parts = StringHashMap::<u32>::with_seed(joined.seed());
+        for bigram in words.windows(2) {
+            *joined.get_or_create(&bigram.join(" "), 0) += 1;
+            *parts.get_or_create_parts(bigram, " ", 0) += 1;
+        }
+        assert_eq!(parts.string_data, joined.string_data);
+        assert!(parts.iter().eq(joined.iter()));
+
+        parts.set_hash_function(hasher::HashFunction::Crc32c);
+        assert_eq!(
+            parts.get_or_create_parts(&["of", "the"], " ", 0),
+            joined.get_or_create("of the", 0)
+        );
+        assert_eq!(*parts.get_or_create_parts(&["of", "the"], "", 7), 7);
+        assert_eq!(*parts.get_or_create_parts(&[], " ", 8), 8);
+        assert_eq!(*parts.get_or_create("", 0), 8);
+    }
+    #[test]
+    fn seeds() {
+        let keys = (0..100).map(|i| i.to_string()).collect::<Vec<_>>();
+        let build = |mut map: StringHashMap<u32>| {
+            for key in &keys {
+                map.get_or_create(key, 0);
+            }
+            map
+        };
+        let first = build(StringHashMap::with_seed(1));
+        let second = build(StringHashMap::with_seed(1));
+        assert_eq!(first.seed(), 1);
+        assert!(first.iter().eq(second.iter()));
+        assert!(!first.iter().eq(build(StringHashMap::with_seed(2)).iter()));
+
+        let seeds: Vec<u32> = (0..4).map(|_| StringHashMap::<u32>::new().seed()).collect();
+        assert!(seeds.iter().any(|&seed| seed != seeds[0]));
+    }
+    #[test]
+    fn rebuild_on_colliding_keys() {
+        let map = StringHashMap::<u32>::with_seed(1);
+        let bitshift = map.bitshift;
+        let home = map.hash("0") >> bitshift;
+        let colliding: Vec<String> = (0..)
+            .map(|i: u32| i.to_string())
+            .filter(|key| map.hash(key) >> bitshift == home)
+            .take(20)
+            .collect();
+        let build = |mut map: StringHashMap<u32>| {
+            map.set_max_probe_length(8);
+            for (i, key) in colliding.iter().enumerate() {
+                map.get_or_create(key, i as u32);
+            }
+            map
+        };
+        let mut map = build(map);
+        assert_eq!(map.rebuilds(), 1);
+        assert_ne!(map.seed(), 1);
+        // a fixed seed stays deterministic across rebuilds
+        let again = build(StringHashMap::with_seed(1));
+        assert_eq!(again.seed(), map.seed());
+        assert!(again.iter().eq(map.iter()));
+        assert_eq!(map.occupied, 20);
+        for (i, key) in colliding.iter().enumerate() {
+            assert_eq!(map.get_or_create(key, 0), &(i as u32));
+        }
+    }
+    #[test]
+    fn rebuild_on_colliding_crc32c_keys() {
+        // CRC32C is affine for keys of one length, `crc(base ^ diff) = crc(base) ^ lin(diff)`, so
+        // the differences in the kernel of `lin` give keys colliding under every seed. Only the
+        // two low bits of each byte of the base vary, which keeps the keys ASCII.
+        let base = [b'a'; 24];
+        let base_crc = hasher::crc32c(&base);
+        let mut rows: Vec<(u32, u64)> = vec![];
+        let mut kernel = vec![];
+        for bit in 0..48 {
+            let mut key = base;
+            key[bit / 2] ^= 1 << (bit % 2);
+            let (mut crc, mut combination) = (hasher::crc32c(&key) ^ base_crc, 1u64 << bit);
+            for &(row_crc, row_combination) in &rows {
+                if crc ^ row_crc < crc {
+                    crc ^= row_crc;
+                    combination ^= row_combination;
+                }
+            }
+            if crc == 0 {
+                kernel.push(combination);
+            } else {
+                rows.push((crc, combination));
+                rows.sort_unstable_by_key(|&(row_crc, _)| std::cmp::Reverse(row_crc));
+            }
+        }
+        let colliding: Vec<String> = (0..200u32)
+            .map(|i| {
+                let mut key = base;
+                for (j, combination) in kernel.iter().enumerate() {
+                    if i & (1 << j) != 0 {
+                        for bit in 0..48 {
+                            if combination & (1 << bit) != 0 {
+                                key[bit / 2] ^= 1 << (bit % 2);
+                            }
+                        }
+                    }
+                }
+                String::from_utf8(key.to_vec()).unwrap()
+            })
+            .collect();
+        for key in &colliding[1..] {
+            assert_eq!(hasher::crc32c(key.as_bytes()), base_crc);
+        }
+
+        let mut map = StringHashMap::<u32>::with_seed(1);
+        map.set_hash_function(hasher::HashFunction::Crc32c);
+        map.set_max_probe_length(32);
+        for (i, key) in colliding.iter().enumerate() {
+            map.get_or_create(key, i as u32);
+        }
+        assert_eq!(map.rebuilds(), 1);
+        assert_eq!(map.hash_function(), hasher::HashFunction::Yoshimitsu);
+        assert_eq!(map.occupied, colliding.len());
+        for (i, key) in colliding.iter().enumerate() {
+            assert_eq!(map.get(key), Some(&(i as u32)));
+        }
+    }
+
     #[test]
     fn get_values() {
         let mut hashmap = StringHashMap::<u32>::new();
@@ -0,0 +1,157 @@
+//! Keys made of a `u32` namespace and a string, e.g. a field id and a term.
+
+use crate::key::Namespaced;
+use crate::StringHashMap;
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+use vint32::decode_varint_slice;
+
+/// `StringHashMap` with `(namespace, &str)` keys, all namespaces share one `string_data`.
+///
+/// The namespace is stored as a varint in front of the key bytes and hashed with them, so a
+/// small namespace costs one byte per key. Such keys are not valid `&str` keys of a plain
+/// `StringHashMap`, that's why the map is wrapped.
+///
+/// ```
+/// use inohashmap::NamespacedStringHashMap;
+/// let mut map = NamespacedStringHashMap::<u32>::new();
+/// *map.get_or_create(0, "blub", 0) += 1;
+/// *map.get_or_create(1, "blub", 0) += 2;
+/// assert_eq!(map.namespace_len(1), 1);
+/// assert_eq!(map.iter_namespace(1).collect::<Vec<_>>(), vec![("blub", &2)]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct NamespacedStringHashMap<T> {
+    map: StringHashMap<T>,
+    /// number of keys per namespace
+    counts: BTreeMap<u32, usize>,
+}
+
+impl<T: Default + Clone + Debug> NamespacedStringHashMap<T> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_power_of_two_size(10)
+    }
+
+    #[inline]
+    pub fn with_power_of_two_size(power_of_two: usize) -> Self {
+        NamespacedStringHashMap {
+            map: StringHashMap::with_power_of_two_size(power_of_two),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn get_or_create(&mut self, namespace: u32, el: &str, value: T) -> &mut T {
+        let key = Namespaced::new(namespace, el);
+        let (value, inserted) = self.map.get_or_create_key(&key, value);
+        if inserted {
+            *self.counts.entry(namespace).or_insert(0) += 1;
+        }
+        value
+    }
+
+    /// Iterates over all keys and their values, in table order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str, &T)> {
+        self.map
+            .table
+            .iter()
+            .filter(|entry| !entry.pointer.is_null())
+            .map(move |entry| {
+                let (namespace, el) = split_key(self.map.read_key_bytes(entry.pointer));
+                (namespace, el, &entry.value)
+            })
+    }
+
+    /// Iterates over the keys of `namespace` and their values. Scans the whole table.
+    pub fn iter_namespace(&self, namespace: u32) -> impl Iterator<Item = (&str, &T)> {
+        self.iter()
+            .filter(move |(key_namespace, _, _)| *key_namespace == namespace)
+            .map(|(_, el, value)| (el, value))
+    }
+}
+
+impl<T> NamespacedStringHashMap<T> {
+    /// Number of keys in all namespaces.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.occupied
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of keys in `namespace`.
+    #[inline]
+    pub fn namespace_len(&self, namespace: u32) -> usize {
+        self.counts.get(&namespace).copied().unwrap_or(0)
+    }
+
+    /// The namespaces with at least one key and their number of keys, in ascending order.
+    pub fn namespaces(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        self.counts
+            .iter()
+            .map(|(&namespace, &len)| (namespace, len))
+    }
+}
+
+impl<T: Default + Clone + Debug> Default for NamespacedStringHashMap<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits stored key bytes into the namespace and the key.
+#[inline]
+fn split_key(bytes: &[u8]) -> (u32, &str) {
+    let mut pos = 0;
+    let namespace = decode_varint_slice(bytes, &mut pos).unwrap();
+    // the bytes after the namespace were written from a `&str`
+    (namespace, unsafe {
+        std::str::from_utf8_unchecked(&bytes[pos..])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn namespaces() {
+        let contents = read_corpus();
+        let namespaces = [0, 1, 127, 128, 300, u32::MAX];
+        let mut map = NamespacedStringHashMap::<u32>::new();
+        let mut expected = vec![StringHashMap::<u32>::new(); namespaces.len()];
+        for (i, text) in contents.split_whitespace().enumerate() {
+            let index = i % namespaces.len();
+            *map.get_or_create(namespaces[index], text, 0) += 1;
+            *expected[index].get_or_create(text, 0) += 1;
+        }
+
+        let total: usize = expected.iter().map(|map| map.occupied).sum();
+        assert_eq!(map.len(), total);
+        assert_eq!(map.iter().count(), total);
+        assert_eq!(
+            map.namespaces().collect::<Vec<_>>(),
+            namespaces
+                .iter()
+                .zip(&expected)
+                .map(|(&namespace, map)| (namespace, map.occupied))
+                .collect::<Vec<_>>()
+        );
+        for (&namespace, expected) in namespaces.iter().zip(&expected) {
+            assert_eq!(map.namespace_len(namespace), expected.occupied);
+            let mut keys: Vec<_> = map.iter_namespace(namespace).collect();
+            let mut expected_keys: Vec<_> = expected.iter().collect();
+            keys.sort();
+            expected_keys.sort();
+            assert_eq!(keys, expected_keys);
+        }
+        assert_eq!(map.namespace_len(2), 0);
+        assert_eq!(map.iter_namespace(2).count(), 0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Counting tokens of a corpus with thread-local maps.
+
+use crate::hasher::{fnv32a_yoshimitsu_triad, DEFAULT_SEED};
+use crate::StringHashMap;
+use std::thread::{self, ScopedJoinHandle};
+
+/// Counts the tokens returned by `tokenize` for every chunk, using `n_threads` threads.
+///
+/// Every thread counts a contiguous range of `chunks` into its own maps, one per partition of
+/// the hash space. The partitions are then merged in parallel, each folding the maps of the
+/// threads in order, and finally appended to each other. Partitions are picked by the hash
+/// modulo `n_threads`, not by its high bits, for the reason given on
+/// [`ShardedStringHashMap`](crate::ShardedStringHashMap).
+///
+/// All maps use `DEFAULT_SEED`, so the id assignment is deterministic, see
+/// `parallel_count_with_seed`.
+///
+/// ```
+/// use inohashmap::parallel_count;
+/// let chunks = ["a b c", "b c", "c"];
+/// let mut counts = parallel_count(&chunks, 2, |chunk| chunk.split_whitespace());
+/// assert_eq!(*counts.get_or_create("c", 0), 3);
+/// ```
+pub fn parallel_count<'a, C, F, I>(
+    chunks: &'a [C],
+    n_threads: usize,
+    tokenize: F,
+) -> StringHashMap<u32>
+where
+    C: AsRef<str> + Sync,
+    F: Fn(&'a str) -> I + Sync,
+    I: Iterator<Item = &'a str>,
+{
+    parallel_count_with_seed(chunks, n_threads, DEFAULT_SEED, tokenize)
+}
+
+/// `parallel_count` with maps created by `StringHashMap::with_seed(seed)`.
+///
+/// No step depends on thread scheduling, so the same `chunks`, `n_threads` and `seed` always
+/// give the same map, including its iteration order and the layout of its string data.
+pub fn parallel_count_with_seed<'a, C, F, I>(
+    chunks: &'a [C],
+    n_threads: usize,
+    seed: u32,
+    tokenize: F,
+) -> StringHashMap<u32>
+where
+    C: AsRef<str> + Sync,
+    F: Fn(&'a str) -> I + Sync,
+    I: Iterator<Item = &'a str>,
+{
+    let n_threads = n_threads.max(1);
+    let chunks_per_thread = chunks.len().div_ceil(n_threads).max(1);
+    let tokenize = &tokenize;
+
+    let thread_maps: Vec<Vec<StringHashMap<u32>>> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .chunks(chunks_per_thread)
+            .map(|thread_chunks| {
+                scope.spawn(move || {
+                    let mut partitions: Vec<StringHashMap<u32>> = (0..n_threads)
+                        .map(|_| StringHashMap::with_seed(seed))
+                        .collect();
+                    for chunk in thread_chunks {
+                        for token in tokenize(chunk.as_ref()) {
+                            let hash = fnv32a_yoshimitsu_triad(seed, token.as_bytes());
+                            let partition = &mut partitions[hash as usize % n_threads];
+                            *partition.get_or_create_with_hash(token, seed, hash, 0) += 1;
+                        }
+                    }
+                    partitions
+                })
+            })
+            .collect();
+        handles.into_iter().map(join).collect()
+    });
+
+    let mut by_partition: Vec<Vec<StringHashMap<u32>>> = (0..n_threads).map(|_| vec![]).collect();
+    for partitions in thread_maps {
+        for (partition, map) in partitions.into_iter().enumerate() {
+            by_partition[partition].push(map);
+        }
+    }
+    let merged: Vec<StringHashMap<u32>> = thread::scope(|scope| {
+        let handles: Vec<_> = by_partition
+            .into_iter()
+            .map(|maps| scope.spawn(move || merge_counts(maps, seed)))
+            .collect();
+        handles.into_iter().map(join).collect()
+    });
+    merge_counts(merged, seed)
+}
+
+/// Merges `maps` in order into the first one.
+fn merge_counts(maps: Vec<StringHashMap<u32>>, seed: u32) -> StringHashMap<u32> {
+    let mut maps = maps.into_iter();
+    let mut map = maps
+        .next()
+        .unwrap_or_else(|| StringHashMap::with_seed(seed));
+    for other in maps {
+        map.merge_from(other, |count, other_count| *count += other_count);
+    }
+    map
+}
+
+fn join<T>(handle: ScopedJoinHandle<'_, T>) -> T {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn counts_are_exact_and_deterministic() {
+        let contents = read_corpus();
+        let chunks: Vec<&str> = contents.lines().collect();
+        let mut expected = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *expected.get_or_create(text, 0) += 1;
+        }
+
+        let mut map = parallel_count(&chunks, 4, |chunk| chunk.split_whitespace());
+        assert_eq!(map.occupied, expected.occupied);
+        for (text, count) in expected.iter() {
+            assert_eq!(map.get_or_create(text, 0), count);
+        }
+        let again = parallel_count(&chunks, 4, |chunk| chunk.split_whitespace());
+        assert_eq!(map.string_data, again.string_data);
+
+        let first = parallel_count_with_seed(&chunks, 3, 7, |chunk| chunk.split_whitespace());
+        let second = parallel_count_with_seed(&chunks, 3, 7, |chunk| chunk.split_whitespace());
+        assert_eq!(first.seed(), 7);
+        assert_eq!(first.string_data, second.string_data);
+        assert!(first.iter().eq(second.iter()));
+    }
+
+    #[test]
+    fn empty() {
+        let chunks: [String; 0] = [];
+        let map = parallel_count(&chunks, 4, |chunk| chunk.split_whitespace());
+        assert_eq!(map.occupied, 0);
+        let map = parallel_count(&["a"], 0, |chunk| chunk.split_whitespace());
+        assert_eq!(map.occupied, 1);
+    }
+}
@@ -0,0 +1,285 @@
+//! Minimal perfect hash export of a finished `StringHashMap`.
+//!
+//! Uses hash and displace (CHD): keys are grouped into buckets by one hash, then every bucket
+//! searches a displacement `(d1, d2)` that moves all its keys to free slots of `[0, n)` with
+//! `slot = (d2 + f1 * d1 + f2) % n`. All three hashes are `fnv32a_yoshimitsu_triad` with
+//! different seeds. The keys stay in `string_data`, so lookups of unknown keys are rejected.
+
+use crate::bytesref::BytesRef;
+use crate::hasher::{fnv32a_yoshimitsu_triad, DEFAULT_SEED};
+use crate::{read_key_bytes_from, read_string_from, StringHashMap};
+use core::fmt::Debug;
+
+/// Number of seeds tried before giving up, each try is very likely to succeed.
+const MAX_SEED_ATTEMPTS: u32 = 64;
+
+/// Values of `d1` tried per bucket before a seed is given up. Every `d1` is tried with all
+/// `d2`, so a bucket tries at most `MAX_D1 * n` displacements instead of `n * n`.
+const MAX_D1: u32 = 256;
+
+/// Builds a `PerfectHashMap` from a `StringHashMap`.
+#[derive(Debug, Clone)]
+pub struct PerfectHashBuilder {
+    keys_per_bucket: usize,
+    seed: u32,
+}
+
+impl Default for PerfectHashBuilder {
+    fn default() -> Self {
+        PerfectHashBuilder {
+            keys_per_bucket: 5,
+            seed: DEFAULT_SEED,
+        }
+    }
+}
+
+impl PerfectHashBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Average number of keys per bucket. Higher values need fewer displacements, but take
+    /// longer to build. Defaults to 5.
+    pub fn keys_per_bucket(mut self, keys_per_bucket: usize) -> Self {
+        assert!(keys_per_bucket > 0, "keys_per_bucket must be positive");
+        self.keys_per_bucket = keys_per_bucket;
+        self
+    }
+
+    /// First seed to try. Builds are deterministic for the same seed and keys.
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Consumes `map`, reusing its `string_data` and values.
+    ///
+    /// # Panics
+    /// If no displacement is found for `MAX_SEED_ATTEMPTS` seeds, which does not happen for
+    /// distinct keys in practice.
+    pub fn build<T: Default + Clone + Debug>(self, map: StringHashMap<T>) -> PerfectHashMap<T> {
+        let StringHashMap {
+            string_data, table, ..
+        } = map;
+        let (pointers, values): (Vec<BytesRef>, Vec<T>) = table
+            .into_iter()
+            .filter(|entry| !entry.pointer.is_null())
+            .map(|entry| (entry.pointer, entry.value))
+            .unzip();
+        let keys: Vec<&[u8]> = pointers
+            .iter()
+            .map(|pointer| read_key_bytes_from(&string_data, *pointer))
+            .collect();
+        let num_buckets = keys.len().div_ceil(self.keys_per_bucket).max(1);
+
+        let mut seed = self.seed;
+        for _ in 0..MAX_SEED_ATTEMPTS {
+            if let Some((displacements, slots)) = find_displacements(&keys, num_buckets, seed) {
+                let mut slot_pointers = vec![BytesRef::default(); slots.len()];
+                let mut slot_values: Vec<Option<T>> = vec![None; slots.len()];
+                for ((pointer, value), slot) in pointers.into_iter().zip(values).zip(slots) {
+                    slot_pointers[slot as usize] = pointer;
+                    slot_values[slot as usize] = Some(value);
+                }
+                return PerfectHashMap {
+                    string_data,
+                    pointers: slot_pointers,
+                    values: slot_values.into_iter().map(Option::unwrap).collect(),
+                    displacements,
+                    seed,
+                };
+            }
+            seed = seed.wrapping_add(1);
+        }
+        panic!("no perfect hash found after {} seeds", MAX_SEED_ATTEMPTS);
+    }
+}
+
+/// `(d1, d2)` per bucket.
+type Displacements = Vec<(u32, u32)>;
+
+/// Returns the displacements per bucket and the slot of every key, or `None` if `seed` fails.
+fn find_displacements(
+    keys: &[&[u8]],
+    num_buckets: usize,
+    seed: u32,
+) -> Option<(Displacements, Vec<u32>)> {
+    let num_slots = keys.len() as u32;
+    let hashes: Vec<Hashes> = keys.iter().map(|key| Hashes::compute(seed, key)).collect();
+
+    let mut buckets: Vec<Vec<u32>> = vec![vec![]; num_buckets];
+    for (key_index, hashes) in hashes.iter().enumerate() {
+        buckets[hashes.bucket as usize % num_buckets].push(key_index as u32);
+    }
+    let mut bucket_order: Vec<usize> = (0..num_buckets).collect();
+    bucket_order.sort_by_key(|&bucket| std::cmp::Reverse(buckets[bucket].len()));
+
+    let mut displacements = vec![(0, 0); num_buckets];
+    let mut key_slots = vec![0u32; keys.len()];
+    let mut occupied = vec![false; keys.len()];
+    // slots claimed by the current try of a bucket, marked with the try's generation
+    let mut claimed = vec![0u64; keys.len()];
+    let mut generation = 0u64;
+
+    for bucket in bucket_order {
+        let bucket_keys = &buckets[bucket];
+        if bucket_keys.is_empty() {
+            break;
+        }
+        let mut placed = false;
+        'search: for d1 in 0..num_slots.min(MAX_D1) {
+            for d2 in 0..num_slots {
+                generation += 1;
+                let fits = bucket_keys.iter().all(|&key_index| {
+                    let slot = hashes[key_index as usize].slot(d1, d2, num_slots) as usize;
+                    if occupied[slot] || claimed[slot] == generation {
+                        return false;
+                    }
+                    claimed[slot] = generation;
+                    true
+                });
+                if fits {
+                    for &key_index in bucket_keys {
+                        let slot = hashes[key_index as usize].slot(d1, d2, num_slots);
+                        occupied[slot as usize] = true;
+                        key_slots[key_index as usize] = slot;
+                    }
+                    displacements[bucket] = (d1, d2);
+                    placed = true;
+                    break 'search;
+                }
+            }
+        }
+        if !placed {
+            return None;
+        }
+    }
+    Some((displacements, key_slots))
+}
+
+struct Hashes {
+    bucket: u32,
+    f1: u32,
+    f2: u32,
+}
+
+impl Hashes {
+    #[inline]
+    fn compute(seed: u32, key: &[u8]) -> Hashes {
+        Hashes {
+            bucket: fnv32a_yoshimitsu_triad(seed, key),
+            f1: fnv32a_yoshimitsu_triad(seed ^ 0x9E37_79B9, key),
+            f2: fnv32a_yoshimitsu_triad(seed ^ 0x85EB_CA6B, key),
+        }
+    }
+
+    #[inline]
+    fn slot(&self, d1: u32, d2: u32, num_slots: u32) -> u32 {
+        d2.wrapping_add(self.f1.wrapping_mul(d1))
+            .wrapping_add(self.f2)
+            % num_slots
+    }
+}
+
+/// Read-only map from keys to dense indices in `[0, len)`, see `PerfectHashBuilder`.
+#[derive(Debug, Clone)]
+pub struct PerfectHashMap<T> {
+    string_data: Vec<u8>,
+    /// key of every index
+    pointers: Vec<BytesRef>,
+    /// value of every index
+    values: Vec<T>,
+    displacements: Displacements,
+    seed: u32,
+}
+
+impl<T> PerfectHashMap<T> {
+    /// Number of keys, all indices are below it.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the dense index of `el`, or `None` if it was not in the map.
+    #[inline]
+    pub fn index(&self, el: &str) -> Option<usize> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let hashes = Hashes::compute(self.seed, el.as_bytes());
+        let (d1, d2) = self.displacements[hashes.bucket as usize % self.displacements.len()];
+        let index = hashes.slot(d1, d2, self.values.len() as u32) as usize;
+        if read_key_bytes_from(&self.string_data, self.pointers[index]) == el.as_bytes() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, el: &str) -> Option<&T> {
+        self.index(el).map(|index| &self.values[index])
+    }
+
+    /// Returns the key at `index`.
+    #[inline]
+    pub fn key(&self, index: usize) -> &str {
+        read_string_from(&self.string_data, self.pointers[index])
+    }
+
+    /// Values ordered by index.
+    #[inline]
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn dense_indices_for_all_words() {
+        let contents = read_corpus();
+        let mut map = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *map.get_or_create(text, 0) += 1;
+        }
+        let num_words = map.occupied;
+        let mut expected = StringHashMap::<u32>::new();
+        for (key, value) in map.iter() {
+            *expected.get_or_create(key, 0) = *value;
+        }
+
+        let perfect = PerfectHashBuilder::new().build(map);
+        assert_eq!(perfect.len(), num_words);
+        let mut seen = vec![false; num_words];
+        for (key, value) in expected.iter() {
+            let index = perfect.index(key).unwrap();
+            assert!(!seen[index]);
+            seen[index] = true;
+            assert_eq!(perfect.key(index), key);
+            assert_eq!(perfect.values()[index], *value);
+        }
+        assert_eq!(perfect.get("not a word in the text"), None);
+    }
+
+    #[test]
+    fn small_and_empty() {
+        let perfect = PerfectHashBuilder::new().build(StringHashMap::<u32>::new());
+        assert!(perfect.is_empty());
+        assert_eq!(perfect.get("blub"), None);
+
+        let mut map = StringHashMap::<u32>::new();
+        map.get_or_create("blub", 7);
+        let perfect = PerfectHashBuilder::new().keys_per_bucket(1).build(map);
+        assert_eq!(perfect.get("blub"), Some(&7));
+        assert_eq!(perfect.get("blub1"), None);
+    }
+}
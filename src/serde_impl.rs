@@ -0,0 +1,245 @@
+//! `Serialize`/`Deserialize` for `StringHashMap`, enabled with the `serde` feature.
+//!
+//! Human-readable formats get a plain map of key to value.
+//! Binary formats get the compact tuple `(string_data, entries)`, where each entry is the
+//! `(addr, value)` of a key in `string_data`. The table itself is never serialized, it is rebuilt
+//! with the current hasher on deserialization.
+
+use crate::bytesref::BytesRef;
+use crate::{decode_varint_checked, power_of_two_for_capacity, StringHashMap, TableEntry};
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeTuple, Serializer};
+
+impl<T: Serialize + Default + Clone + Debug> Serialize for StringHashMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let mut map = serializer.serialize_map(Some(self.occupied))?;
+            for (key, value) in self.iter() {
+                map.serialize_entry(key, value)?;
+            }
+            map.end()
+        } else {
+            let mut tuple = serializer.serialize_tuple(2)?;
+            tuple.serialize_element(&Bytes(&self.string_data))?;
+            tuple.serialize_element(&Entries(self))?;
+            tuple.end()
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Default + Clone + Debug> Deserialize<'de> for StringHashMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_map(MapVisitor(PhantomData))
+        } else {
+            let (ByteBuf(string_data), entries): (ByteBuf, Vec<(u32, T)>) =
+                Deserialize::deserialize(deserializer)?;
+            StringHashMap::from_compact(string_data, entries)
+        }
+    }
+}
+
+impl<T: Default + Clone + Debug> StringHashMap<T> {
+    /// Rebuilds the table from serialized `string_data` and `(addr, value)` entries.
+    ///
+    /// The sorted addresses must be exactly the starts of the records in `string_data`.
+    fn from_compact<E: de::Error>(
+        string_data: Vec<u8>,
+        mut entries: Vec<(u32, T)>,
+    ) -> Result<Self, E> {
+        if string_data.len() > u32::MAX as usize {
+            return Err(E::custom("string_data exceeds u32::MAX bytes"));
+        }
+        entries.sort_unstable_by_key(|&(addr, _)| addr);
+        let mut map = Self::with_power_of_two_size(power_of_two_for_capacity(entries.len()));
+        map.string_data = string_data;
+        // start of the next record in `string_data`
+        let mut record_start = 0;
+        for (addr, value) in entries {
+            if addr as usize != record_start {
+                return Err(E::custom(format_args!("invalid key address {}", addr)));
+            }
+            let mut pos = record_start;
+            let text = decode_varint_checked(&map.string_data, &mut pos)
+                .and_then(|len| map.string_data.get(pos..pos.checked_add(len as usize)?))
+                .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                .ok_or_else(|| E::custom(format_args!("invalid key address {}", addr)))?;
+            record_start = pos + text.len();
+            let mut probe = map.get_probe(text.as_bytes());
+            let mut hash = probe.next_probe() as usize;
+            loop {
+                let pointer = map.table[hash].pointer;
+                if pointer.is_null() {
+                    map.table[hash] = TableEntry {
+                        value,
+                        pointer: BytesRef(addr),
+                    };
+                    map.occupied += 1;
+                    break;
+                } else if map.read_string(pointer) == text {
+                    return Err(E::custom(format_args!("duplicate key {:?}", text)));
+                }
+                hash = probe.next_probe() as usize;
+            }
+        }
+        if record_start != map.string_data.len() {
+            return Err(E::custom("string_data contains keys without an entry"));
+        }
+        Ok(map)
+    }
+}
+
+struct MapVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de> + Default + Clone + Debug> Visitor<'de> for MapVisitor<T> {
+    type Value = StringHashMap<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of strings to values")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        // cap the preallocation, the size hint comes from untrusted input
+        let capacity = access.size_hint().unwrap_or(0).min(1 << 20);
+        let mut map = StringHashMap::with_power_of_two_size(power_of_two_for_capacity(capacity));
+        while let Some((key, value)) = access.next_entry::<String, T>()? {
+            let occupied = map.occupied;
+            map.get_or_create(&key, value);
+            if map.occupied == occupied {
+                return Err(de::Error::custom(format_args!("duplicate key {:?}", key)));
+            }
+        }
+        Ok(map)
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct Entries<'a, T>(&'a StringHashMap<T>);
+
+impl<T: Serialize + Default + Clone + Debug> Serialize for Entries<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.occupied))?;
+        for entry in self.0.table.iter().filter(|entry| !entry.pointer.is_null()) {
+            seq.serialize_element(&(entry.pointer.addr(), &entry.value))?;
+        }
+        seq.end()
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("string data bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(bytes.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(bytes))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(1 << 20));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(ByteBuf(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_words() -> StringHashMap<u32> {
+        let mut map = StringHashMap::<u32>::new();
+        for text in "the quick brown fox jumps over the lazy dog the end".split_whitespace() {
+            *map.get_or_create(text, 0) += 1;
+        }
+        map
+    }
+
+    fn assert_same(left: &StringHashMap<u32>, right: &mut StringHashMap<u32>) {
+        assert_eq!(left.occupied, right.occupied);
+        for (key, value) in left.iter() {
+            assert_eq!(right.get_or_create(key, u32::MAX), value);
+        }
+        assert_eq!(left.occupied, right.occupied);
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let map = count_words();
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.contains("\"the\":3"));
+        let mut deserialized: StringHashMap<u32> = serde_json::from_str(&json).unwrap();
+        assert_same(&map, &mut deserialized);
+    }
+
+    #[test]
+    fn bincode_roundtrip() {
+        let mut map = StringHashMap::<u32>::with_power_of_two_size(1);
+        for i in 0..10_000u32 {
+            *map.get_or_create(&i.to_string(), i) += 1;
+        }
+        let bytes = bincode::serialize(&map).unwrap();
+        let mut deserialized: StringHashMap<u32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(deserialized.string_data, map.string_data);
+        assert_same(&map, &mut deserialized);
+    }
+
+    #[test]
+    fn bincode_rejects_invalid_entries() {
+        let map = count_words();
+        let (string_data, mut entries): (Vec<u8>, Vec<(u32, u32)>) =
+            bincode::deserialize(&bincode::serialize(&map).unwrap()).unwrap();
+
+        let mut out_of_bounds = entries.clone();
+        out_of_bounds[0].0 = string_data.len() as u32;
+        let bytes = bincode::serialize(&(&string_data, out_of_bounds)).unwrap();
+        assert!(bincode::deserialize::<StringHashMap<u32>>(&bytes).is_err());
+
+        let mut inside_record = entries.clone();
+        let last = inside_record.iter().map(|entry| entry.0).max().unwrap();
+        inside_record.retain(|entry| entry.0 != last);
+        inside_record.push((last + 1, 0));
+        let bytes = bincode::serialize(&(&string_data, inside_record)).unwrap();
+        assert!(bincode::deserialize::<StringHashMap<u32>>(&bytes).is_err());
+
+        let missing_record = entries[1..].to_vec();
+        let bytes = bincode::serialize(&(&string_data, missing_record)).unwrap();
+        assert!(bincode::deserialize::<StringHashMap<u32>>(&bytes).is_err());
+
+        entries.push(entries[0]);
+        let bytes = bincode::serialize(&(&string_data, entries)).unwrap();
+        assert!(bincode::deserialize::<StringHashMap<u32>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn json_rejects_duplicate_keys() {
+        let json = r#"{"blub":1,"blub":2}"#;
+        assert!(serde_json::from_str::<StringHashMap<u32>>(json).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! `StringHashMap` split into independently locked shards, for counting from many threads.
+
+use crate::hasher::{fnv32a_yoshimitsu_triad, random_seed};
+use crate::StringHashMap;
+use core::fmt::Debug;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Concurrent `StringHashMap`, every key lives in exactly one of N shards.
+///
+/// Keys are routed by the low bits of `fnv32a_yoshimitsu_triad`, seeded randomly per instance
+/// unless created `with_seed`, which gives the same shard for a key in every run.
+/// The shards share that seed and index their table with the high bits of the same hash, which
+/// would all be equal within a shard if they were used for routing too. The hash is computed
+/// once per call, unless the shard was rebuilt with its own seed.
+#[derive(Debug)]
+pub struct ShardedStringHashMap<T> {
+    shards: Box<[Mutex<StringHashMap<T>>]>,
+    mask: u32,
+    seed: u32,
+}
+
+impl<T: Default + Clone + Debug> ShardedStringHashMap<T> {
+    /// Creates `num_shards` shards, rounded up to a power of two.
+    pub fn new(num_shards: usize) -> Self {
+        Self::with_power_of_two_size(num_shards, 10)
+    }
+
+    /// Creates `num_shards` shards, rounded up to a power of two, each created with
+    /// `StringHashMap::with_power_of_two_size(power_of_two)`.
+    pub fn with_power_of_two_size(num_shards: usize, power_of_two: usize) -> Self {
+        let mut sharded =
+            Self::with_power_of_two_size_and_seed(num_shards, power_of_two, random_seed());
+        for shard in sharded.shards.iter_mut() {
+            shard.get_mut().unwrap().fixed_seed = false;
+        }
+        sharded
+    }
+
+    /// `new` with a fixed seed, see `with_power_of_two_size_and_seed`.
+    pub fn with_seed(num_shards: usize, seed: u32) -> Self {
+        Self::with_power_of_two_size_and_seed(num_shards, 10, seed)
+    }
+
+    /// Creates `num_shards` shards, rounded up to a power of two, each created with
+    /// `StringHashMap::with_power_of_two_size_and_seed(power_of_two, seed)`. The shard of a key
+    /// only depends on `seed` and the number of shards.
+    pub fn with_power_of_two_size_and_seed(
+        num_shards: usize,
+        power_of_two: usize,
+        seed: u32,
+    ) -> Self {
+        let num_shards = num_shards.max(1).next_power_of_two();
+        assert!(num_shards <= 1 << 16, "at most 65536 shards are supported");
+        let shards = (0..num_shards)
+            .map(|_| {
+                Mutex::new(StringHashMap::with_power_of_two_size_and_seed(
+                    power_of_two,
+                    seed,
+                ))
+            })
+            .collect();
+        ShardedStringHashMap {
+            shards,
+            mask: num_shards as u32 - 1,
+            seed,
+        }
+    }
+
+    /// The seed used to route keys to shards.
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    #[inline]
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Inserts `value` if `el` is new, then calls `f` on the value of `el` while its shard is
+    /// locked.
+    #[inline]
+    pub fn get_or_create_with<R>(&self, el: &str, value: T, f: impl FnOnce(&mut T) -> R) -> R {
+        let hash = fnv32a_yoshimitsu_triad(self.seed, el.as_bytes());
+        let mut shard = lock(&self.shards[(hash & self.mask) as usize]);
+        f(shard.get_or_create_with_hash(el, self.seed, hash, value))
+    }
+
+    /// Number of keys in all shards. Locks the shards one after another.
+    pub fn len(&self) -> usize {
+        self.shards().map(|shard| shard.occupied).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Locks and yields the shards one after another.
+    pub fn shards(&self) -> impl Iterator<Item = MutexGuard<'_, StringHashMap<T>>> {
+        self.shards.iter().map(lock)
+    }
+
+    pub fn into_shards(self) -> Vec<StringHashMap<T>> {
+        self.shards
+            .into_vec()
+            .into_iter()
+            .map(|shard| shard.into_inner().unwrap_or_else(PoisonError::into_inner))
+            .collect()
+    }
+
+    /// Merges all shards into one map.
+    pub fn into_map(self) -> StringHashMap<T> {
+        let mut shards = self.into_shards().into_iter();
+        let mut map = shards.next().unwrap();
+        for shard in shards {
+            map.merge_from(shard, |_, _| unreachable!("shards have disjoint keys"));
+        }
+        map
+    }
+}
+
+/// A panic in `get_or_create_with` can only leave a half updated value behind, the map itself is
+/// still valid, so poisoning is ignored.
+#[inline]
+fn lock<T>(shard: &Mutex<T>) -> MutexGuard<'_, T> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn count_from_threads() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let mut expected = StringHashMap::<u32>::new();
+        for text in &words {
+            *expected.get_or_create(text, 0) += 1;
+        }
+
+        let sharded = ShardedStringHashMap::<u32>::new(6);
+        assert_eq!(sharded.num_shards(), 8);
+        std::thread::scope(|scope| {
+            for chunk in words.chunks(words.len() / 4 + 1) {
+                let sharded = &sharded;
+                scope.spawn(move || {
+                    for text in chunk {
+                        sharded.get_or_create_with(text, 0, |value| *value += 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(sharded.len(), expected.occupied);
+        assert!(sharded.shards().all(|shard| shard.occupied > 0));
+
+        let mut map = sharded.into_map();
+        assert_eq!(map.occupied, expected.occupied);
+        for (text, value) in expected.iter() {
+            assert_eq!(map.get_or_create(text, 0), value);
+        }
+    }
+
+    #[test]
+    fn fixed_seed() {
+        let shards_of = |sharded: ShardedStringHashMap<u32>| {
+            for i in 0..1000 {
+                sharded.get_or_create_with(&i.to_string(), 0, |_| {});
+            }
+            sharded
+                .into_shards()
+                .iter()
+                .map(|shard| shard.string_data.clone())
+                .collect::<Vec<_>>()
+        };
+        let sharded = ShardedStringHashMap::<u32>::with_seed(4, 7);
+        assert_eq!(sharded.seed(), 7);
+        assert_eq!(
+            shards_of(sharded),
+            shards_of(ShardedStringHashMap::with_seed(4, 7))
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! One writer, many readers, without locks on the read path.
+//!
+//! `StringHashMap::into_split` keeps two copies of the map. Readers take snapshots of the
+//! published copy while the writer inserts into the other one. `Writer::publish` swaps the two,
+//! waits until no snapshot of the old copy is left and replays the changes into it.
+//!
+//! Holding a snapshot for a long time blocks the next `publish` and so the writer, it never
+//! blocks other readers.
+
+use crate::bytesref::BytesRef;
+use crate::{read_string_from, StringHashMap};
+use core::cell::UnsafeCell;
+use core::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+struct Shared<T> {
+    maps: [UnsafeCell<StringHashMap<T>>; 2],
+    /// Index of the published map.
+    read_index: AtomicUsize,
+    /// Epochs of all readers, odd while a snapshot is alive.
+    readers: Mutex<Vec<Arc<AtomicUsize>>>,
+}
+
+// The published map is only read through `&`, the other one is only accessed by the writer.
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn readers(&self) -> MutexGuard<'_, Vec<Arc<AtomicUsize>>> {
+        self.readers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: Default + Clone + Debug> StringHashMap<T> {
+    /// Splits the map into a `Writer` and a `ReaderFactory`, both start with the current
+    /// contents published.
+    pub fn into_split(self) -> (Writer<T>, ReaderFactory<T>) {
+        let shared = Arc::new(Shared {
+            maps: [UnsafeCell::new(self.clone()), UnsafeCell::new(self)],
+            read_index: AtomicUsize::new(0),
+            readers: Mutex::new(vec![]),
+        });
+        let writer = Writer {
+            shared: Arc::clone(&shared),
+            dirty: vec![],
+            dirty_bits: vec![],
+        };
+        (writer, ReaderFactory { shared })
+    }
+}
+
+/// The single writer, changes become visible to readers with `publish`.
+///
+/// `publish` waits for the snapshots of the map published before, so a long lived snapshot
+/// blocks the writer in `publish`. `get_or_create` never waits.
+pub struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    /// Addresses of the keys touched since the last `publish`, in the writer's map.
+    dirty: Vec<u32>,
+    /// Bit per byte of the writer's `string_data`, set for the addresses in `dirty`.
+    dirty_bits: Vec<u64>,
+}
+
+impl<T: Default + Clone + Debug> Writer<T> {
+    #[inline]
+    pub fn get_or_create(&mut self, el: &str, value: T) -> &mut T {
+        let write_index = 1 - self.shared.read_index.load(Ordering::Relaxed);
+        // Only the writer accesses the map that is not published.
+        let map = unsafe { &mut *self.shared.maps[write_index].get() };
+        let entry = map.get_or_create_entry(el, map.hash(el), value);
+        let addr = entry.pointer.addr();
+        let (word, bit) = (addr as usize / 64, 1 << (addr % 64));
+        if word >= self.dirty_bits.len() {
+            self.dirty_bits.resize(word + 1, 0);
+        }
+        if self.dirty_bits[word] & bit == 0 {
+            self.dirty_bits[word] |= bit;
+            self.dirty.push(addr);
+        }
+        &mut entry.value
+    }
+
+    /// The writer's map, including the changes that are not published yet.
+    #[inline]
+    pub fn map(&self) -> &StringHashMap<T> {
+        let write_index = 1 - self.shared.read_index.load(Ordering::Relaxed);
+        unsafe { &*self.shared.maps[write_index].get() }
+    }
+
+    /// Makes all changes visible to new snapshots.
+    ///
+    /// Waits for the snapshots of the previously published map to be dropped, then copies the
+    /// keys and values touched since the last call into it. Blocks as long as such a snapshot
+    /// is alive.
+    pub fn publish(&mut self) {
+        let old_index = self.shared.read_index.load(Ordering::Relaxed);
+        self.shared
+            .read_index
+            .store(1 - old_index, Ordering::SeqCst);
+        // A reader holding a snapshot may wait for the lock to create or drop another reader,
+        // so don't hold it while waiting.
+        let readers = self.shared.readers().clone();
+        for epoch in &readers {
+            let current = epoch.load(Ordering::SeqCst);
+            if current % 2 == 1 {
+                while epoch.load(Ordering::Acquire) == current {
+                    std::thread::yield_now();
+                }
+            }
+        }
+
+        // No reader can see the old map anymore.
+        let source = unsafe { &*self.shared.maps[1 - old_index].get() };
+        let target = unsafe { &mut *self.shared.maps[old_index].get() };
+        // Sorting by address replays the keys in insertion order.
+        self.dirty.sort_unstable();
+        for &addr in &self.dirty {
+            let key = read_string_from(&source.string_data, BytesRef(addr));
+            let value = source.get(key).expect("dirty key is in the map").clone();
+            *target.get_or_create(key, T::default()) = value;
+            self.dirty_bits[addr as usize / 64] = 0;
+        }
+        self.dirty.clear();
+    }
+}
+
+/// Creates `Reader`s, can be cloned and sent to other threads.
+pub struct ReaderFactory<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for ReaderFactory<T> {
+    fn clone(&self) -> Self {
+        ReaderFactory {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> ReaderFactory<T> {
+    pub fn reader(&self) -> Reader<T> {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        self.shared.readers().push(Arc::clone(&epoch));
+        Reader {
+            shared: Arc::clone(&self.shared),
+            epoch,
+        }
+    }
+}
+
+/// Takes snapshots of the published map, one at a time.
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl<T> Reader<T> {
+    /// The map as of the last `Writer::publish`, it doesn't change while the snapshot is alive.
+    #[inline]
+    pub fn snapshot(&mut self) -> Snapshot<'_, T> {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        let read_index = self.shared.read_index.load(Ordering::SeqCst);
+        Snapshot {
+            map: unsafe { &*self.shared.maps[read_index].get() },
+            epoch: &self.epoch,
+        }
+    }
+}
+
+impl<T> Drop for Reader<T> {
+    fn drop(&mut self) {
+        self.shared
+            .readers()
+            .retain(|epoch| !Arc::ptr_eq(epoch, &self.epoch));
+    }
+}
+
+/// Read access to the published map.
+pub struct Snapshot<'a, T> {
+    map: &'a StringHashMap<T>,
+    epoch: &'a AtomicUsize,
+}
+
+impl<'a, T: Default + Clone + Debug> Snapshot<'a, T> {
+    #[inline]
+    pub fn get(&self, el: &str) -> Option<&T> {
+        self.map.get(el)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.map.iter()
+    }
+}
+
+impl<'a, T> Snapshot<'a, T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.occupied
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a, T> Drop for Snapshot<'a, T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.epoch.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    #[test]
+    fn publish_makes_changes_visible() {
+        let mut map = StringHashMap::<u32>::new();
+        map.get_or_create("a", 1);
+        let (mut writer, readers) = map.into_split();
+        let mut reader = readers.reader();
+
+        *writer.get_or_create("a", 0) += 1;
+        writer.get_or_create("b", 5);
+        for _ in 0..100 {
+            writer.get_or_create("b", 0);
+        }
+        assert_eq!(writer.dirty.len(), 2);
+        assert_eq!(writer.map().occupied, 2);
+        {
+            let snapshot = reader.snapshot();
+            assert_eq!(snapshot.len(), 1);
+            assert_eq!(snapshot.get("a"), Some(&1));
+            assert_eq!(snapshot.get("b"), None);
+        }
+
+        writer.publish();
+        let snapshot = reader.snapshot();
+        assert_eq!(snapshot.get("a"), Some(&2));
+        assert_eq!(snapshot.get("b"), Some(&5));
+        drop(snapshot);
+
+        // The replayed map has the changes too.
+        writer.get_or_create("c", 7);
+        writer.publish();
+        let snapshot = reader.snapshot();
+        let mut contents: Vec<_> = snapshot.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+        contents.sort();
+        assert_eq!(
+            contents,
+            vec![
+                ("a".to_string(), 2),
+                ("b".to_string(), 5),
+                ("c".to_string(), 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn add_and_drop_readers_during_publish() {
+        let (mut writer, readers) = StringHashMap::<u32>::new().into_split();
+        let mut reader = readers.reader();
+        let other_reader = readers.reader();
+        let snapshot = reader.snapshot();
+        std::thread::scope(|scope| {
+            let publisher = scope.spawn(|| {
+                writer.get_or_create("a", 1);
+                writer.publish();
+            });
+            // wait until `publish` waits for `snapshot`
+            while readers.shared.read_index.load(Ordering::SeqCst) == 0 {
+                std::thread::yield_now();
+            }
+            let mut new_reader = readers.reader();
+            drop(other_reader);
+            drop(snapshot);
+            publisher.join().unwrap();
+            assert_eq!(new_reader.snapshot().get("a"), Some(&1));
+        });
+    }
+
+    #[test]
+    fn read_while_writing() {
+        let contents = read_corpus();
+        let words: Vec<&str> = contents.split_whitespace().collect();
+        let (mut writer, readers) = StringHashMap::<u32>::new().into_split();
+        let published = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                let mut reader = readers.reader();
+                let published = &published;
+                let words = &words;
+                scope.spawn(move || loop {
+                    let num_words = published.load(Ordering::Acquire);
+                    let snapshot = reader.snapshot();
+                    for word in &words[..num_words] {
+                        assert!(snapshot.get(word).is_some());
+                    }
+                    if num_words == words.len() {
+                        break;
+                    }
+                });
+            }
+            for (batch_end, batch) in words.chunks(10_000).scan(0, |end, batch| {
+                *end += batch.len();
+                Some((*end, batch))
+            }) {
+                for word in batch {
+                    *writer.get_or_create(word, 0) += 1;
+                }
+                writer.publish();
+                published.store(batch_end, Ordering::Release);
+            }
+        });
+
+        let mut reader = readers.reader();
+        let snapshot = reader.snapshot();
+        assert_eq!(snapshot.len(), writer.map().occupied);
+        for (word, count) in writer.map().iter() {
+            assert_eq!(snapshot.get(word), Some(count));
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! Sorted term dictionary, written from a `StringHashMap` and read from a byte slice.
+//!
+//! # Layout
+//!
+//! All integers in the header and block index are little-endian `u32`.
+//!
+//! ```text
+//! header:      magic "INOT" | num_terms | block_size | value_size | num_blocks | terms_len
+//! block index: num_blocks * offset of the block in terms
+//! values:      num_terms * value_size    (FixedSizeValue encoding, in term order)
+//! terms:       terms_len bytes
+//! ```
+//!
+//! Terms are sorted by bytes and front coded in blocks of `block_size` terms. Every term is
+//! stored as varint shared prefix length, varint suffix length and the suffix bytes. The first
+//! term of a block shares nothing with its predecessor, so blocks can be binary searched.
+
+use crate::frozen::{invalid_data, read_u32_le, FixedSizeValue};
+use crate::{decode_varint_checked, StringHashMap};
+use core::cmp::Ordering;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use std::io::{self, Write};
+use vint32::encode_varint_into;
+
+const MAGIC: &[u8; 4] = b"INOT";
+const HEADER_LEN: usize = 6 * 4;
+
+/// Writes the keys and values of a `StringHashMap` as a `TermDictionary`.
+#[derive(Debug, Clone)]
+pub struct TermDictionaryWriter {
+    block_size: usize,
+}
+
+impl Default for TermDictionaryWriter {
+    fn default() -> Self {
+        TermDictionaryWriter { block_size: 16 }
+    }
+}
+
+impl TermDictionaryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of terms per front coded block. Larger blocks are more compact, but lookups decode
+    /// more terms. Defaults to 16.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        assert!(block_size > 0, "block_size must be positive");
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn write<T, W>(&self, map: &StringHashMap<T>, mut out: W) -> io::Result<()>
+    where
+        T: Default + Clone + Debug + FixedSizeValue,
+        W: Write,
+    {
+        let mut terms: Vec<(&str, &T)> = map.iter().collect();
+        terms.sort_unstable_by(|left, right| left.0.cmp(right.0));
+
+        let mut block_offsets = vec![];
+        let mut term_bytes = vec![];
+        let mut values = Vec::with_capacity(terms.len() * T::SIZE);
+        let mut previous: &[u8] = &[];
+        for (ordinal, (term, value)) in terms.iter().enumerate() {
+            let term = term.as_bytes();
+            let shared = if ordinal % self.block_size == 0 {
+                block_offsets.push(term_bytes.len() as u32);
+                0
+            } else {
+                common_prefix_len(previous, term)
+            };
+            encode_varint_into(&mut term_bytes, shared as u32);
+            encode_varint_into(&mut term_bytes, (term.len() - shared) as u32);
+            term_bytes.extend_from_slice(&term[shared..]);
+            value.write_to(&mut values);
+            previous = term;
+        }
+        if term_bytes.len() > u32::MAX as usize {
+            return Err(invalid_data("terms exceed u32::MAX bytes"));
+        }
+
+        out.write_all(MAGIC)?;
+        for field in [
+            terms.len() as u32,
+            self.block_size as u32,
+            T::SIZE as u32,
+            block_offsets.len() as u32,
+            term_bytes.len() as u32,
+        ] {
+            out.write_all(&field.to_le_bytes())?;
+        }
+        for offset in block_offsets {
+            out.write_all(&offset.to_le_bytes())?;
+        }
+        out.write_all(&values)?;
+        out.write_all(&term_bytes)
+    }
+}
+
+fn common_prefix_len(left: &[u8], right: &[u8]) -> usize {
+    left.iter()
+        .zip(right)
+        .take_while(|(left, right)| left == right)
+        .count()
+}
+
+/// Read-only sorted dictionary borrowing the output of `TermDictionaryWriter`.
+///
+/// Every term has an ordinal, its position in sorted order.
+#[derive(Debug)]
+pub struct TermDictionary<'a, T> {
+    num_terms: usize,
+    block_size: usize,
+    block_index: &'a [u8],
+    values: &'a [u8],
+    terms: &'a [u8],
+    _value: PhantomData<T>,
+}
+
+impl<T> Clone for TermDictionary<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TermDictionary<'_, T> {}
+
+impl<'a, T: FixedSizeValue> TermDictionary<'a, T> {
+    /// Checks the header and section sizes of `bytes`. The sections are borrowed, not copied.
+    pub fn new(bytes: &'a [u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not a term dictionary"));
+        }
+        let field = |i: usize| read_u32_le(&bytes[4 + i * 4..]) as usize;
+        let (num_terms, block_size, value_size) = (field(0), field(1), field(2));
+        let (num_blocks, terms_len) = (field(3), field(4));
+        if value_size != T::SIZE {
+            return Err(invalid_data("value size does not match"));
+        }
+        if block_size == 0 || num_blocks != num_terms.div_ceil(block_size) {
+            return Err(invalid_data("invalid block index"));
+        }
+        let index_end = HEADER_LEN + num_blocks * 4;
+        let values_end = index_end + num_terms * value_size;
+        if bytes.len() != values_end + terms_len {
+            return Err(invalid_data("length does not match header"));
+        }
+        Ok(TermDictionary {
+            num_terms,
+            block_size,
+            block_index: &bytes[HEADER_LEN..index_end],
+            values: &bytes[index_end..values_end],
+            terms: &bytes[values_end..],
+            _value: PhantomData,
+        })
+    }
+
+    /// Number of terms.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.num_terms
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.num_terms == 0
+    }
+
+    /// Returns the value stored for `el`.
+    pub fn get(&self, el: &str) -> Option<T> {
+        self.ordinal(el).map(|ordinal| self.value(ordinal))
+    }
+
+    /// Returns the ordinal of `el`.
+    pub fn ordinal(&self, el: &str) -> Option<u32> {
+        let mut cursor = self.block_cursor(self.find_block(el.as_bytes()));
+        while cursor.advance() {
+            match cursor.term.as_slice().cmp(el.as_bytes()) {
+                Ordering::Less => {}
+                Ordering::Equal => return Some(cursor.ordinal() as u32),
+                Ordering::Greater => return None,
+            }
+        }
+        None
+    }
+
+    /// Returns the term with `ordinal`.
+    pub fn term(&self, ordinal: u32) -> Option<String> {
+        let ordinal = ordinal as usize;
+        if ordinal >= self.num_terms {
+            return None;
+        }
+        let mut cursor = self.block_cursor(ordinal / self.block_size);
+        for _ in 0..=ordinal % self.block_size {
+            if !cursor.advance() {
+                return None;
+            }
+        }
+        String::from_utf8(cursor.term).ok()
+    }
+
+    /// Returns the value of the term with `ordinal`.
+    ///
+    /// # Panics
+    /// If `ordinal` is not below `len()`.
+    #[inline]
+    pub fn value(&self, ordinal: u32) -> T {
+        let start = ordinal as usize * T::SIZE;
+        T::read_from(&self.values[start..start + T::SIZE])
+    }
+
+    /// Iterates in sorted order over all terms starting with `prefix`.
+    pub fn prefix(&self, prefix: &str) -> PrefixIter<'a, T> {
+        PrefixIter {
+            dictionary: *self,
+            cursor: self.block_cursor(self.find_block(prefix.as_bytes())),
+            prefix: prefix.as_bytes().to_vec(),
+        }
+    }
+
+    /// Last block whose first term is not greater than `target`.
+    fn find_block(&self, target: &[u8]) -> usize {
+        let num_blocks = self.block_index.len() / 4;
+        let mut low = 0;
+        let mut high = num_blocks;
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.first_term(mid).is_none_or(|term| term <= target) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low.saturating_sub(1)
+    }
+
+    fn first_term(&self, block: usize) -> Option<&'a [u8]> {
+        let mut cursor = self.block_cursor(block);
+        let _shared = decode_varint_checked(self.terms, &mut cursor.pos)?;
+        let suffix_len = decode_varint_checked(self.terms, &mut cursor.pos)? as usize;
+        self.terms
+            .get(cursor.pos..cursor.pos.checked_add(suffix_len)?)
+    }
+
+    fn block_cursor(&self, block: usize) -> BlockCursor<'a> {
+        let pos = if block * 4 < self.block_index.len() {
+            read_u32_le(&self.block_index[block * 4..]) as usize
+        } else {
+            self.terms.len()
+        };
+        BlockCursor {
+            terms: self.terms,
+            pos,
+            term: vec![],
+            next_ordinal: block * self.block_size,
+            num_terms: self.num_terms,
+        }
+    }
+}
+
+/// Decodes front coded terms one after another, continuing into the following blocks.
+struct BlockCursor<'a> {
+    terms: &'a [u8],
+    pos: usize,
+    term: Vec<u8>,
+    next_ordinal: usize,
+    num_terms: usize,
+}
+
+impl BlockCursor<'_> {
+    /// Decodes the next term into `term`. Returns false at the end or on corrupt data.
+    fn advance(&mut self) -> bool {
+        if self.next_ordinal >= self.num_terms || !self.decode_term() {
+            self.next_ordinal = self.num_terms;
+            return false;
+        }
+        self.next_ordinal += 1;
+        true
+    }
+
+    fn decode_term(&mut self) -> bool {
+        let (shared, suffix_len) = match (
+            decode_varint_checked(self.terms, &mut self.pos),
+            decode_varint_checked(self.terms, &mut self.pos),
+        ) {
+            (Some(shared), Some(suffix_len)) => (shared as usize, suffix_len as usize),
+            _ => return false,
+        };
+        let suffix = match self
+            .terms
+            .get(self.pos..self.pos.saturating_add(suffix_len))
+        {
+            Some(suffix) if shared <= self.term.len() => suffix,
+            _ => return false,
+        };
+        self.term.truncate(shared);
+        self.term.extend_from_slice(suffix);
+        self.pos += suffix_len;
+        true
+    }
+
+    /// Ordinal of the current term.
+    #[inline]
+    fn ordinal(&self) -> usize {
+        self.next_ordinal - 1
+    }
+}
+
+/// Iterator over `(term, value)` for all terms with a prefix, see `TermDictionary::prefix`.
+pub struct PrefixIter<'a, T> {
+    dictionary: TermDictionary<'a, T>,
+    cursor: BlockCursor<'a>,
+    prefix: Vec<u8>,
+}
+
+impl<T: FixedSizeValue> Iterator for PrefixIter<'_, T> {
+    type Item = (String, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.cursor.advance() {
+            let term = &self.cursor.term;
+            if term.as_slice() < self.prefix.as_slice() {
+                continue;
+            }
+            if !term.starts_with(&self.prefix) {
+                self.cursor.next_ordinal = self.cursor.num_terms;
+                return None;
+            }
+            let value = self.dictionary.value(self.cursor.ordinal() as u32);
+            return String::from_utf8(term.clone())
+                .ok()
+                .map(|term| (term, value));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_corpus;
+
+    fn count_words() -> StringHashMap<u32> {
+        let contents = read_corpus();
+        let mut map = StringHashMap::<u32>::new();
+        for text in contents.split_whitespace() {
+            *map.get_or_create(text, 0) += 1;
+        }
+        map
+    }
+
+    #[test]
+    fn lookups() {
+        let map = count_words();
+        let mut bytes = vec![];
+        TermDictionaryWriter::new()
+            .block_size(7)
+            .write(&map, &mut bytes)
+            .unwrap();
+        let dictionary = TermDictionary::<u32>::new(&bytes).unwrap();
+        assert_eq!(dictionary.len(), map.occupied);
+
+        let mut sorted: Vec<(&str, &u32)> = map.iter().collect();
+        sorted.sort();
+        for (ordinal, (term, value)) in sorted.iter().enumerate() {
+            assert_eq!(dictionary.ordinal(term), Some(ordinal as u32));
+            assert_eq!(dictionary.get(term), Some(**value));
+            assert_eq!(dictionary.term(ordinal as u32).as_deref(), Some(*term));
+        }
+        assert_eq!(dictionary.term(sorted.len() as u32), None);
+        assert_eq!(dictionary.get(""), None);
+        assert_eq!(dictionary.get("not a word in the text"), None);
+        assert_eq!(dictionary.get("\u{10FFFF}"), None);
+    }
+
+    #[test]
+    fn prefix() {
+        let map = count_words();
+        let mut bytes = vec![];
+        TermDictionaryWriter::new().write(&map, &mut bytes).unwrap();
+        let dictionary = TermDictionary::<u32>::new(&bytes).unwrap();
+
+        let mut sorted: Vec<(&str, &u32)> = map.iter().collect();
+        sorted.sort();
+        for prefix in ["", "Eliz", "a", "the", "zz", "\u{10FFFF}"] {
+            let expected: Vec<(String, u32)> = sorted
+                .iter()
+                .filter(|(term, _)| term.starts_with(prefix))
+                .map(|(term, value)| (term.to_string(), **value))
+                .collect();
+            assert_eq!(dictionary.prefix(prefix).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut bytes = vec![];
+        TermDictionaryWriter::new()
+            .write(&StringHashMap::<u64>::new(), &mut bytes)
+            .unwrap();
+        let dictionary = TermDictionary::<u64>::new(&bytes).unwrap();
+        assert!(dictionary.is_empty());
+        assert_eq!(dictionary.get("blub"), None);
+        assert_eq!(dictionary.prefix("").count(), 0);
+        assert!(TermDictionary::<u32>::new(&bytes).is_err());
+    }
+}